@@ -0,0 +1,75 @@
+//! Compares the output modes that format straight into the writer with the ones that build a
+//! `String` first, counting allocations along the way.
+//!
+//! Run with `cargo bench`. Output goes to `io::sink()`, so this measures eztrace itself rather
+//! than the terminal.
+
+#[macro_use]
+extern crate eztrace;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const ROUNDS: usize = 100_000;
+
+fn bench<F: FnMut(usize)>(name: &str, mut f: F) {
+    // Warm up, so one-time setup isn't counted.
+    for i in 0..1000 {
+        f(i);
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for i in 0..ROUNDS {
+        f(i);
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    println!(
+        "{:<24} {:>8.1?}/trace {:>6.2} allocations/trace",
+        name,
+        elapsed / ROUNDS as u32,
+        allocations as f64 / ROUNDS as f64,
+    );
+}
+
+fn main() {
+    let pair = (12, "twelve");
+    eztrace::set_sink(io::sink());
+
+    bench("write_fmt", |i| trace!(i, pair));
+    bench("write_fmt, pretty", |i| trace!(#i, pair));
+
+    eztrace::enable_ring(100);
+    bench("ring", |i| trace!(i, pair));
+    eztrace::disable_ring();
+
+    eztrace::set_prefix_fn(Box::new(|| String::from("[worker] ")));
+    bench("prefix fn", |i| trace!(i, pair));
+    eztrace::clear_prefix_fn();
+
+    eztrace::set_pretty_indent(2);
+    bench("pretty, reindented", |i| trace!(#i, pair));
+    eztrace::set_pretty_indent(4);
+
+    eztrace::reset_sink();
+}
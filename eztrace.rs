@@ -19,10 +19,26 @@
 //! ```text
 //! my_variable, other_variable: 42 237
 //! ```
+//!
+//! Output goes to stdout, unless it's sent elsewhere with [`set_sink`] or [`set_channel`], or
+//! kept for later by [`enable_realtime_buffer`].
+//!
+//! Traced values are formatted while the output is locked, so a `trace!` made from inside a
+//! traced value's `Debug` or `Display` impl, on the same thread, is dropped rather than waited
+//! on forever.
+//!
+//! There's no compile-time way to send output to a function of yours instead. A `macro_rules!`
+//! macro can't see items in your crate unless every call names them, and stable Rust has no
+//! weak symbols, so a function found by name at link time would have to be defined in every
+//! binary that links eztrace: each test, each doctest, and every program whose dependencies
+//! turn on such a feature, since Cargo unifies features across the build. Use [`set_sink`]
+//! with a `Write` that calls your function; it's one lock and a dynamic call per trace.
 
 /// Prints out variables and their debug representation.
 ///
-/// Non-`Copy` types do what you would hope.
+/// Non-`Copy` types do what you would hope. Arguments are borrowed rather than moved, and
+/// references print the same as the values behind them, so unsized things like `*slice`,
+/// `str`, or `dyn Debug` trait objects work whether or not you dereference them.
 ///
 /// # Examples
 ///
@@ -39,6 +55,22 @@
 /// // a * a + b * b, c * c: 25 25
 /// ```
 ///
+/// Each expression is labelled with its source text, as `stringify!` renders it. Raw
+/// identifiers stay raw (`r#type`), tuple fields (`pair.0`), method calls (`v.iter().count()`)
+/// and indexing (`v[0]`) come out without spaces. Spacing elsewhere, as around operators, is
+/// up to the compiler and differs between Rust versions. Comments are dropped, and line breaks
+/// become spaces. Braces in the expression, as in struct literals or blocks, are printed as
+/// they are.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// let r#type = "raw";
+/// let pair = (1, 2);
+/// let v = vec![pair];
+/// trace!(r#type, pair.0, v.iter().count(), v[0], pair.0 + pair.1);
+/// // r#type, pair.0, v.iter().count(), v[0], pair.0 + pair.1: "raw" 1 1 (1, 2) 3
+/// ```
+///
 /// To print just the file & line:
 ///
 /// ```
@@ -47,6 +79,128 @@
 /// // eztrace.rs:1
 /// ```
 ///
+/// [`set_show_location(false)`](set_show_location) silences these, for when you only want
+/// your labels, and [`set_here_marker`] replaces them with a breadcrumb of your own.
+///
+/// To print the file & line followed by a short message:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// trace!(@here "reached cleanup");
+/// // eztrace.rs:1: reached cleanup
+/// ```
+///
+/// To mark the moment something happened, `@now` prints the file & line with the wall-clock
+/// time, optionally after a label:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// trace!(@now);
+/// // eztrace.rs:1: 2024-01-02 15:04:05.123 UTC
+/// trace!(@now "connected");
+/// // eztrace.rs:3: connected: 2024-01-02 15:04:05.124 UTC
+/// ```
+///
+/// The time comes from `SystemTime::now()`, shown to the millisecond. It's always UTC: working
+/// out local time needs the system's time zone database, which `std` doesn't offer.
+///
+/// For constants, `@const` makes sure what you're tracing really is one: each argument goes in
+/// a `const { }` block, so it's evaluated at compile time, and anything that can't be is a
+/// compile error rather than a surprise:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// const PAGE: usize = 4096;
+/// trace!(@const PAGE, PAGE * 16, u16::MAX);
+/// // PAGE, PAGE * 16, u16::MAX: 4096 65536 65535
+/// ```
+///
+/// Only the values are worked out at compile time; they're still formatted and printed at
+/// run time, as `Debug` can't run in a `const` context. For the same reason `trace!` can't be
+/// used inside a `const fn` or a `const` item. Local variables aren't constants, even ones
+/// that never change:
+///
+/// ```compile_fail
+/// # #[macro_use] extern crate eztrace;
+/// let page = 4096;
+/// trace!(@const page);
+/// ```
+///
+/// Ordinarily every argument is evaluated before anything is printed, so if one of them panics,
+/// the others are lost too. `@stream` prints each argument on its own line as soon as it's
+/// evaluated instead:
+///
+/// ```should_panic
+/// # #[macro_use] extern crate eztrace;
+/// let v = vec![1, 2, 3];
+/// trace!(@stream v.len(), v[0], v[10], v[1]);
+/// // v.len(): 3
+/// // v[0]: 1
+/// // (and then the panic)
+/// ```
+///
+/// To check what an environment variable is set to, `@env` prints it with the file & line.
+/// Values are quoted, so an empty value looks different from one that isn't set:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// trace!(@env "HOME", "EDITOR", "NO_SUCH_VARIABLE");
+/// // eztrace.rs:1: HOME = "/home/bob"
+/// // eztrace.rs:1: EDITOR = ""
+/// // eztrace.rs:1: NO_SUCH_VARIABLE = <unset>
+/// ```
+///
+/// To find the same trace across runs or log files, `@id` tags it with a short id for its call
+/// site. Give it a label to hash instead, and the id survives the code moving around:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// let x = 5;
+/// trace!(@id x);
+/// // [34c4] x: 5
+/// trace!(@id("parse header") x);
+/// // [4009] x: 5
+/// ```
+///
+/// The id is the 32-bit FNV-1a hash of `file:line` (or of the label), folded to 16 bits and
+/// worked out at compile time. With only 65536 ids to go round, expect a collision or two once
+/// there are a few hundred tagged call sites; it's for grepping, not for telling sites apart
+/// with certainty.
+///
+/// In a terminal that understands OSC 8 hyperlinks, `@link` puts a clickable file & line in
+/// front of the values:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// let x = 5;
+/// trace!(@link x);
+/// // eztrace.rs:2: x: 5
+/// ```
+///
+/// The link points at `file:///` plus the source file's absolute path. `file!()` is usually
+/// relative to where Cargo was run, so it's taken to be relative to the `EZTRACE_SRC_DIR`
+/// environment variable if that's set, and to the current directory otherwise; set
+/// `EZTRACE_SRC_DIR` to your workspace root if the program runs from somewhere else. Links are
+/// left out, leaving plain `file:line`, unless stdout is a terminal, `NO_COLOR` isn't set, and
+/// the trace is printed straight to stdout: output to a [`set_sink`], [`add_tee`] or
+/// [`set_channel`], and output that's batched, filtered, buffered or kept in the ring, always
+/// gets the plain form.
+///
+/// To tell warnings apart from routine traces at a glance, `@warn`, `@info` and `@err` put a
+/// marker in front of a label or of the usual `name: value` line:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// let retries = 3;
+/// trace!(@warn retries);
+/// // [WARN] retries: 3
+/// trace!(@err "giving up");
+/// // [ERR] giving up
+/// ```
+///
+/// With the `icons` feature these are `⚠`, `ℹ` and `✗` instead; [`set_icons`] switches at runtime,
+/// e.g. back to ASCII for a terminal that can't show them.
+///
 /// To print a label:
 ///
 /// ```
@@ -56,12 +210,21 @@
 /// ```
 ///
 /// You can also prefix with a `#` to get `{:#?}`-style format codes (tho you might just use
-/// `dbg!()` instead…)
+/// `dbg!()` instead…). On the forms that don't print values, `#` asks for the more precise
+/// version:
+///
+/// | Form               | Prints             | With `#`                  |
+/// |--------------------|--------------------|---------------------------|
+/// | `trace!()`         | `file:line`        | `trace!(#)`: `file:line:column` |
+/// | `trace!("label")`  | the label as is    | `trace!(#"label")`: the label quoted, as `{:?}` |
+/// | `trace!(a, b)`     | `{:?}`, one line   | `trace!(#a, b)`: `{:#?}`, across lines |
+///
+/// Other forms don't take a `#`, except `@#N`, below.
 ///
 /// ```
 /// # #[macro_use] extern crate eztrace;
 /// trace!(#);
-/// // eztrace.rs:1
+/// // eztrace.rs:1:1
 /// trace!(#"hello");
 /// // "hello"
 /// #[derive(Debug, Default)]
@@ -76,26 +239,696 @@
 /// //     y: 0.0,
 /// // }
 /// ```
+///
+/// The pretty form indents by 4 spaces, same as `std`. Use `@#N` to pick a different width
+/// for one call, or [`set_pretty_indent`] to change it everywhere:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// # #[derive(Debug, Default)] struct Coords { x: f32, y: f32 }
+/// let zero = Coords::default();
+/// trace!(@#2 zero);
+/// // zero: Coords {
+/// //   x: 0.0,
+/// //   y: 0.0,
+/// // }
+/// ```
+///
+/// Deeply nested values, like syntax trees, can make for a lot of pretty output. `@#depth(N)`
+/// shows only the first `N` levels inside each value, replacing anything deeper with `…`;
+/// [`set_max_depth`] does the same for every `trace!(#...)`:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// #[derive(Debug)]
+/// enum Expr { Num(i32), Add(Box<Expr>, Box<Expr>) }
+/// let num = |n| Box::new(Expr::Num(n));
+/// let sum = Expr::Add(num(1), Box::new(Expr::Add(num(2), num(3))));
+/// trace!(@#depth(1) sum);
+/// // sum: Add(
+/// //     Num(
+/// //         …
+/// //     ),
+/// //     Add(
+/// //         …
+/// //     ),
+/// // )
+/// ```
+///
+/// Rust's formatter has no depth limit to ask for, so this works on the finished text. In
+/// `{:#?}` output each level is indented 4 spaces more than the last, and `Debug` escapes the
+/// newlines inside strings, so a line's indentation tells its depth. Each run of lines deeper
+/// than `N` becomes one `…`, and the closing brackets that follow are kept. That's exact for
+/// derived `Debug` and the standard collections, but it's only a guess at the structure: a
+/// hand-written `Debug` impl that writes its own newlines or indentation gets cut wherever its
+/// indentation says, and the deep parts are still formatted before they're thrown away.
+///
+/// For a heartbeat in a long loop, `@tick` prints a single `.` without ending the line, and
+/// `@tick N` prints one every `N`th time that call site is reached, with `@tick 0` acting like
+/// `@tick 1`. The next ordinary trace finishes the line of dots first:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// for _ in 0..1000 {
+///     trace!(@tick 100);
+/// }
+/// trace!();
+/// // ..........
+/// // eztrace.rs:5
+/// ```
+///
+/// To sort traces into categories you can switch on and off at compile time, put a `cfg`
+/// predicate in front with `@cfg(...)`. When the predicate is false the trace is compiled out
+/// entirely, arguments and all. The predicate is checked in *your* crate, so the usual way to
+/// use this is to declare a feature per category in your own `Cargo.toml`,
+///
+/// ```text
+/// [features]
+/// trace-io = []
+/// trace-net = []
+/// ```
+///
+/// and give each category a short macro of its own:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// macro_rules! trace_io {
+///     ($($t:tt)*) => { trace!(@cfg(feature = "trace-io") $($t)*) };
+/// }
+/// let bytes_read = 512;
+/// trace_io!(bytes_read);
+/// // (prints only when built with `--features trace-io`)
+/// trace!(@cfg(debug_assertions) "debug build");
+/// ```
+///
+/// Anything `#[cfg]` accepts works, like `all(...)` or `not(...)`. As the trace is compiled out
+/// rather than skipped, `@cfg` can't wrap forms such as `@ret` that need to produce a value.
+///
+/// For traces you want only some of the time, give them a verbosity level with `@level(N)`.
+/// They print only if `N` is at most the current threshold, which [`set_level`] sets and
+/// [`with_level`] raises temporarily. The threshold starts at 0, so plain traces, which are
+/// level 0, always print and everything above stays quiet. Arguments aren't evaluated when
+/// the trace is skipped.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// let packet = [0xde, 0xad];
+/// trace!(@level(2) packet);
+/// // (nothing)
+/// eztrace::with_level(2, || trace!(@level(2) packet));
+/// // packet: [222, 173]
+/// ```
+///
+/// To trace what a function returns, wrap the returned expression in `@ret`. The value is
+/// passed through unchanged:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// fn compute() -> String {
+///     trace!(@ret format!("{}", 17))
+/// }
+/// assert_eq!(compute(), "17");
+/// // eztrace.rs:3: returns "17"
+/// ```
+///
+/// For full control, follow any argument with `=>` and a standard format spec. Arguments
+/// without one still get `{:?}`:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// let (ratio, flags, name) = (0.12345, 0x2a, "bob");
+/// trace!(ratio => ":>8.2", flags => ":#06x", name);
+/// // ratio, flags, name:     0.12 0x002a "bob"
+/// ```
+///
+/// When an expression is too long to make a good name, name it yourself with `"name" =`.
+/// Named and unnamed arguments mix freely, and names work with `=>` too:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// let items = vec![3, 1, 2];
+/// trace!("max" = items.iter().copied().fold(0, i32::max), items.len(), "first" = items[0] => ":03");
+/// // max, items.len(), first: 3 3 003
+/// ```
+///
+/// To show values through several format codes at once, list them in `@fmts(...)`. The first
+/// code is the main rendering and the rest follow in parentheses. `@x` is shorthand for
+/// decimal and hex:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// let n = 255;
+/// let m = 16;
+/// trace!(@fmts("{:?}", "{:#x}", "{:#b}") n);
+/// // n: 255 (0xff, 0b11111111)
+/// trace!(@x n, m);
+/// // n, m: 255 (0xff) 16 (0x10)
+/// ```
+///
+/// Before dumping something huge, `@meta` tells you how big its `{:#?}` output would be:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// let grid = vec![vec![0u8; 3]; 2];
+/// trace!(@meta grid);
+/// // grid: <95 chars, 12 lines>
+/// ```
+///
+/// Measuring means formatting the whole thing, so this costs as much as printing it would,
+/// minus the printing. Nothing is kept, though: the output is counted as it's produced.
+///
+/// To make NaN and infinities stand out, `@nan` flags them with a `⚠`:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// let (x, y, z) = (3.14, 0.0 / 0.0, 1.0 / 0.0);
+/// trace!(@nan x, y, z);
+/// // x, y, z: 3.14 NaN ⚠ +Inf ⚠
+/// let samples = vec![1.0f32, -1.0 / 0.0];
+/// trace!(@nan samples);
+/// // samples: [1.0, -Inf] ⚠
+/// ```
+///
+/// It works on `f32` and `f64`, and on arrays, slices and `Vec`s of them, flagging the whole
+/// collection if any element isn't finite.
+///
+/// For state machines, `@variant` shows only which enum variant a value is, not its payload:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// #[derive(Debug)]
+/// enum State { Idle, Running { pid: u32 }, Done(i32) }
+/// let (a, b, c) = (State::Idle, State::Running { pid: 7 }, State::Done(0));
+/// trace!(@variant a, b, c);
+/// // a, b, c: Idle Running Done
+/// ```
+///
+/// There's no way to ask for a variant's name, so this formats the value with `{:?}` and keeps
+/// everything up to the first `(`, `{`, or whitespace. That's right for derived `Debug` on
+/// enums; hand-written `Debug` impls, and things that aren't enums at all, get whatever their
+/// output happens to start with.
+///
+/// Between the two, `@enum` shows the variant name and the start of its payload:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// #[derive(Debug)]
+/// enum Msg { Quit, Load(Vec<u32>) }
+/// let (a, b) = (Msg::Quit, Msg::Load((0..100).collect()));
+/// trace!(@enum a, b);
+/// // a, b: Quit Load([0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, …
+/// ```
+///
+/// The variant name, found the same way as for `@variant`, is always kept whole. Whatever
+/// follows it in the `{:?}` output is cut after [`set_enum_summary_len`] characters, 40 by
+/// default, and ends with `…` if anything was cut.
+///
+/// When you'd rather not remember which types have which, `@display_or_debug` uses `Display`
+/// for values that have it, and `Debug` for the rest:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// use std::net::Ipv4Addr;
+/// let (addr, ports) = (Ipv4Addr::LOCALHOST, [80, 443]);
+/// trace!(@display_or_debug addr, ports, "text");
+/// // addr, ports, "text": 127.0.0.1 [80, 443] text
+/// ```
+///
+/// Rust has no way to ask whether a type implements a trait, so this picks one the way method
+/// calls do. The value is wrapped, and two traits provide the same method: the `Display` one
+/// for a reference to the wrapper, the `Debug` one for the wrapper itself. Method lookup on a
+/// reference to a reference tries the first before auto-dereferencing to the second, so
+/// `Display` wins when both apply. That decision is made from the type the macro sees where
+/// it's written. Inside a generic function it only knows the bounds: a `T: Debug` is shown
+/// with `Debug` even if the actual type also has `Display`. A type with neither is an error
+/// that mentions `__display_or_debug`.
+///
+/// To keep `Option`s terse, `@opt` shows what's inside a `Some`, and `—` for `None`:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// let (port, host): (Option<u16>, Option<&str>) = (Some(8080), None);
+/// trace!(@opt port, host);
+/// // port, host: 8080 —
+/// ```
+///
+/// [`set_none_marker`] changes what `None` looks like.
+///
+/// Raw numbers can be shown as quantities: `@bytes` for byte counts, and `@ms` or `@us` for
+/// durations counted in milliseconds or microseconds. Any primitive number works.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// let (size, elapsed, latency) = (1572864, 1500, 250u16);
+/// trace!(@bytes size);
+/// // size: 1.5 MiB
+/// trace!(@ms elapsed);
+/// // elapsed: 1.5s
+/// trace!(@us latency);
+/// // latency: 250µs
+/// ```
+///
+/// In a hot loop, `@throttle(ms)` prints at most once every `ms` milliseconds from that call
+/// site. The first call always prints. The arguments are still evaluated every time, so side
+/// effects happen as usual; only the formatting and printing are skipped.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// for frame in 0..1_000_000 {
+///     trace!(@throttle(500) frame);
+/// }
+/// // frame: 0
+/// // (and then about one every half second)
+/// ```
+///
+/// To find out which values reach a spot, rather than how often, `@distinct` prints only
+/// values that call site hasn't printed before:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// for input in &[3, 1, 3, 3, 2, 1] {
+///     trace!(@distinct input);
+/// }
+/// // input: 3
+/// // input: 1
+/// // input: 2
+/// ```
+///
+/// Every distinct value is kept as a `String`, forever. To bound that, each call site stops
+/// remembering new values after [`set_distinct_cap`] of them (1000 by default); values past the
+/// cap print every time.
+///
+/// To watch a counter or accumulator evolve, `@delta` shows how much it changed since the last
+/// time that call site ran on this thread:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// let mut total = 0;
+/// for n in [100, 7, -20] {
+///     total += n;
+///     trace!(@delta total);
+/// }
+/// // total: 100
+/// // total: 107 (+7)
+/// // total: 87 (-20)
+/// ```
+///
+/// The value must be `Copy + PartialOrd + Sub`, with a `'static` type, and the difference
+/// `Debug`; every primitive number qualifies. The difference is always the larger value minus
+/// the smaller, with the sign put in front, so an unsigned value that goes down shows as
+/// `(-2)` rather than underflowing. The first call, and the first after the value's type
+/// changes, has nothing to compare with, so it prints no difference.
+///
+/// To hear about a value only when it changes sign, use `@crossing`:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// for error in [3.0, 1.5, -0.5, -2.0, 0.0, 4.0] {
+///     trace!(@crossing error);
+/// }
+/// // error: -0.5 (+ → -)
+/// // error: 0.0 (- → 0)
+/// // error: 4.0 (0 → +)
+/// ```
+///
+/// The sign is whether the value is above, below or equal to `Default::default()`, so any
+/// `PartialOrd + Default` type works; values that don't compare with it, like NaN, are skipped.
+/// Every change between `+`, `-` and `0` counts as a crossing. The first value a call site sees
+/// on a thread has nothing to cross from, so it's only remembered.
+///
+/// To catch a loop that has stopped making progress, `@stuck(K)` stays quiet until a value's
+/// `Debug` output has come out the same K hits in a row at that call site:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// for progress in [1, 2, 2, 2, 2, 3, 3] {
+///     trace!(@stuck(3) progress);
+/// }
+/// // progress: 2 (same for 3 hits)
+/// ```
+///
+/// Each hit compares against the one before it: the same output extends the streak, anything
+/// else starts a new streak of 1. The trace prints once, on the hit that makes the streak K long,
+/// and not again until the value changes and gets stuck anew. A K of 0 acts like 1, printing
+/// every new value once. Streaks are per thread.
+///
+/// To draw a call tree by hand, `@depth` puts a `│ ` in front of the trace for each level of
+/// [`push_depth`] or [`DepthGuard`] it's inside. It goes in front of any other form, so
+/// `trace!(@depth @ret x)` works too. See [`push_depth`] for an example, and
+/// [`set_depth_marker`] to draw something else.
+///
+/// When two variables seem to alias, `@addr_of` shows where they live. The variables are
+/// borrowed, not moved. Addresses change from run to run, so don't expect these exact ones:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// let buf = vec![0u8; 16];
+/// let view = &buf;
+/// trace!(@addr_of buf, *view);
+/// // &buf, &*view: 0x7ffd5d8a4be0 0x7ffd5d8a4be0
+/// ```
+///
+/// Taking the address of a temporary, like `@addr_of f()`, only tells you where that temporary
+/// happened to be put.
+///
+/// For pasting numbers into a spreadsheet, `@csv` prints just the values, comma separated. The
+/// first time each call site runs it also prints a header row made of the expressions. Values
+/// still use `{:?}`. Any field, value or header, that contains a comma, a quote or a line break
+/// is put in double quotes, with its own quotes doubled, so a traced `Vec` or string stays in
+/// one column:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// let (point, name) = ([1, 2], "a");
+/// trace!(@csv point, name, point.len());
+/// // point,name,point.len()
+/// // "[1, 2]","""a""",2
+/// ```
+///
+/// Plain numbers need no quoting:
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// for i in 0..3 {
+///     trace!(@csv i, i * i);
+/// }
+/// // i,i * i
+/// // 0,0
+/// // 1,1
+/// // 2,4
+/// ```
 #[macro_export]
 macro_rules! trace {
-    () => { println!(trace!(@line)); };
-    (#) => { println!(trace!(@line)); };
+    () => { $crate::__here($crate::trace!(@line)); };
+    (#) => { $crate::__here(concat!($crate::trace!(@line), ":", column!())); };
+    (@here $msg:literal) => {
+        $crate::__print(format_args!("{}: {}", $crate::trace!(@line), $msg));
+    };
     (#$label:literal) => {
-        println!("{:?}", $label);
+        $crate::__print(format_args!("{:?}", $label));
     };
     ($label:literal) => {
-        println!("{}", $label);
+        $crate::__print(format_args!("{}", $label));
     };
-    (#$($IT:expr),* $(,)?) => {
-        println!(
-            trace!(@#fmt $($IT),*),
+    (@tick) => {
+        $crate::__print_partial(format_args!("."))
+    };
+    (@tick $every:expr) => {{
+        static TICKS: ::std::sync::atomic::AtomicUsize = ::std::sync::atomic::AtomicUsize::new(0);
+        let ticks = TICKS.fetch_add(1, ::std::sync::atomic::Ordering::Relaxed) + 1;
+        // `@tick 0` would divide by zero; treat it like `@tick 1`.
+        if ticks % ::std::cmp::max($every, 1) == 0 {
+            $crate::__print_partial(format_args!("."));
+        }
+    }};
+    (@cfg($($pred:tt)*) $($rest:tt)*) => {{
+        #[cfg($($pred)*)]
+        {
+            $crate::trace!($($rest)*);
+        }
+    }};
+    (@level($level:expr) $($rest:tt)*) => {
+        if $crate::__level_enabled($level) {
+            $crate::trace!($($rest)*);
+        }
+    };
+    (@now) => {
+        $crate::__print(format_args!("{}: {}", $crate::trace!(@line), $crate::__Now::now()));
+    };
+    (@now $label:literal) => {
+        $crate::__print(format_args!("{}: {}: {}", $crate::trace!(@line), $label, $crate::__Now::now()));
+    };
+    (@id($label:literal) $($IT:expr),+ $(,)?) => {
+        $crate::trace!(@idprint $label; $($IT),+)
+    };
+    (@id $($IT:expr),+ $(,)?) => {
+        $crate::trace!(@idprint $crate::trace!(@line); $($IT),+)
+    };
+    (@idprint $site:expr; $($IT:expr),+) => {{
+        const ID: u16 = $crate::__site_id($site);
+        $crate::__print(format_args!(
+            concat!("[{:04x}] ", $crate::trace!(@fmt $($IT),*)),
+            ID,
+            $crate::trace!(@stringify $($IT,)*),
+            $($crate::__value(&$IT)),*
+        ));
+    }};
+    (@link $($IT:expr),+ $(,)?) => {
+        $crate::__print(format_args!(
+            concat!("{}: ", $crate::trace!(@fmt $($IT),*)),
+            $crate::__Link::new(file!(), line!()),
+            $crate::trace!(@stringify $($IT,)*),
+            $($crate::__value(&$IT)),*
+        ));
+    };
+    (@warn $($rest:tt)+) => {
+        $crate::trace!(@severity Warn; $($rest)+)
+    };
+    (@info $($rest:tt)+) => {
+        $crate::trace!(@severity Info; $($rest)+)
+    };
+    (@err $($rest:tt)+) => {
+        $crate::trace!(@severity Err; $($rest)+)
+    };
+    (@severity $severity:ident; $label:literal) => {
+        $crate::__print(format_args!("{} {}", $crate::__Severity::$severity, $label));
+    };
+    (@severity $severity:ident; $($IT:expr),+ $(,)?) => {
+        $crate::__print(format_args!(
+            concat!("{} ", $crate::trace!(@fmt $($IT),*)),
+            $crate::__Severity::$severity,
+            $crate::trace!(@stringify $($IT,)*),
+            $($crate::__value(&$IT)),*
+        ));
+    };
+    (@const $($IT:expr),+ $(,)?) => {
+        $crate::__print(format_args!(
+            $crate::trace!(@fmt $($IT),*),
+            $crate::trace!(@stringify $($IT,)*),
+            $($crate::__value(&const { $IT })),*
+        ));
+    };
+    (@stream $($IT:expr),+ $(,)?) => {{
+        $(
+            $crate::__print(format_args!("{}: {:?}", stringify!($IT), $crate::__value(&$IT)));
+        )+
+    }};
+    (@env $($var:expr),+ $(,)?) => {
+        $(
+            match $var {
+                var => $crate::__print(format_args!(
+                    "{}: {} = {}",
+                    $crate::trace!(@line),
+                    var,
+                    $crate::__EnvVar::get(&var),
+                )),
+            }
+        )+
+    };
+    (@ret $IT:expr) => {
+        match $IT {
+            value => {
+                $crate::__print(format_args!("{}: returns {:?}", $crate::trace!(@line), $crate::__value(&value)));
+                value
+            }
+        }
+    };
+    (@throttle($ms:expr) $($IT:expr),+ $(,)?) => {{
+        static LAST: $crate::__Throttle = $crate::__Throttle::new();
+        let ready = LAST.ready($ms);
+        $crate::__print_if(ready, format_args!(
+            $crate::trace!(@fmt $($IT),*),
+            $crate::trace!(@stringify $($IT,)*),
+            $($crate::__value(&$IT)),*
+        ));
+    }};
+    (@distinct $($IT:expr),+ $(,)?) => {{
+        static SEEN: $crate::__Distinct = $crate::__Distinct::new();
+        let values = format!(
+            concat!($($crate::trace!(@fmtcode $IT)),*),
+            $($crate::__value(&$IT)),*
+        );
+        if SEEN.is_new(&values) {
+            $crate::__print(format_args!("{}:{}", $crate::trace!(@stringify $($IT,)*), values));
+        }
+    }};
+    (@addr_of $($IT:expr),+ $(,)?) => {
+        $crate::__print(format_args!(
+            concat!(
+                "{}:",
+                $($crate::trace!(@addrcode $IT)),*
+            ),
+            $crate::trace!(@addrnames $($IT,)*),
             $(&$IT),*
+        ));
+    };
+    (@csv $($IT:expr),+ $(,)?) => {{
+        static HEADER: ::std::sync::Once = ::std::sync::Once::new();
+        HEADER.call_once(|| $crate::__print(format_args!(
+            $crate::trace!(@csvfmt $($IT,)*),
+            $($crate::__CsvField(stringify!($IT))),*
+        )));
+        $crate::__print(format_args!(
+            $crate::trace!(@csvfmt $($IT,)*),
+            $($crate::__CsvField(format!("{:?}", $crate::__value(&$IT)))),*
+        ));
+    }};
+    (@fmts $codes:tt $($IT:expr),+ $(,)?) => {
+        $crate::__print(format_args!(
+            concat!(
+                "{}:",
+                $($crate::trace!(@displaycode $IT)),*
+            ),
+            $crate::trace!(@stringify $($IT,)*),
+            $($crate::trace!(@fmtsvalue $codes $IT)),*
+        ));
+    };
+    (@delta $IT:expr $(,)?) => {{
+        ::std::thread_local!(static LAST: $crate::__Delta = const { $crate::__Delta::new() });
+        let value = $IT;
+        let change = LAST.with(|last| last.update(value));
+        $crate::__print(format_args!("{}: {:?}{}", stringify!($IT), $crate::__value(&value), change));
+    }};
+    (@crossing $IT:expr $(,)?) => {{
+        ::std::thread_local!(static SIGN: $crate::__Crossing = const { $crate::__Crossing::new() });
+        let value = &$IT;
+        if let Some(crossed) = SIGN.with(|sign| sign.update(value)) {
+            $crate::__print(format_args!("{}: {:?} {}", stringify!($IT), $crate::__value(value), crossed));
+        }
+    }};
+    (@stuck($hits:expr) $IT:expr $(,)?) => {{
+        ::std::thread_local!(static STREAK: $crate::__Stuck = const { $crate::__Stuck::new() });
+        let value = &$IT;
+        let debug = format!("{:?}", $crate::__value(value));
+        if let Some(hits) = STREAK.with(|streak| streak.update(debug, $hits)) {
+            $crate::__print(format_args!("{}: {:?} (same for {} hits)", stringify!($IT), $crate::__value(value), hits));
+        }
+    }};
+    (@depth @ret $IT:expr) => {{
+        let _draw = $crate::__DrawDepth::new();
+        $crate::trace!(@ret $IT)
+    }};
+    (@depth $($IT:tt)*) => {{
+        let _draw = $crate::__DrawDepth::new();
+        $crate::trace!($($IT)*);
+    }};
+    (@opt $($IT:expr),+ $(,)?) => {
+        $crate::__print(format_args!(
+            concat!(
+                "{}:",
+                $($crate::trace!(@displaycode $IT)),*
+            ),
+            $crate::trace!(@stringify $($IT,)*),
+            $($crate::__opt(&$IT)),*
+        ));
+    };
+    (@meta $($IT:expr),+ $(,)?) => {
+        $crate::__print(format_args!(
+            concat!(
+                "{}:",
+                $($crate::trace!(@displaycode $IT)),*
+            ),
+            $crate::trace!(@stringify $($IT,)*),
+            $($crate::__Meta($crate::__value(&$IT))),*
+        ));
+    };
+    (@nan $($IT:expr),+ $(,)?) => {
+        $crate::__print(format_args!(
+            concat!(
+                "{}:",
+                $($crate::trace!(@displaycode $IT)),*
+            ),
+            $crate::trace!(@stringify $($IT,)*),
+            $($crate::__Nan(&$IT)),*
+        ));
+    };
+    (@variant $($IT:expr),+ $(,)?) => {
+        $crate::__print(format_args!(
+            concat!(
+                "{}:",
+                $($crate::trace!(@displaycode $IT)),*
+            ),
+            $crate::trace!(@stringify $($IT,)*),
+            $($crate::__Variant($crate::__value(&$IT))),*
+        ));
+    };
+    (@enum $($IT:expr),+ $(,)?) => {
+        $crate::__print(format_args!(
+            concat!(
+                "{}:",
+                $($crate::trace!(@displaycode $IT)),*
+            ),
+            $crate::trace!(@stringify $($IT,)*),
+            $($crate::__Summary($crate::__value(&$IT))),*
+        ));
+    };
+    (@display_or_debug $($IT:expr),+ $(,)?) => {
+        $crate::__print(format_args!(
+            concat!(
+                "{}:",
+                $($crate::trace!(@displaycode $IT)),*
+            ),
+            $crate::trace!(@stringify $($IT,)*),
+            $({
+                #[allow(unused_imports)]
+                use $crate::{__ViaDebug, __ViaDisplay};
+                (&&$crate::__DisplayOrDebug(&$IT)).__display_or_debug()
+            }),*
+        ));
+    };
+    (@bytes $($IT:expr),+ $(,)?) => {
+        $crate::trace!(@quantity __Bytes 1.0; $($IT),+)
+    };
+    (@ms $($IT:expr),+ $(,)?) => {
+        $crate::trace!(@quantity __Nanos 1e6; $($IT),+)
+    };
+    (@us $($IT:expr),+ $(,)?) => {
+        $crate::trace!(@quantity __Nanos 1e3; $($IT),+)
+    };
+    (@quantity $unit:ident $scale:expr; $($IT:expr),+) => {
+        $crate::__print(format_args!(
+            concat!(
+                "{}:",
+                $($crate::trace!(@displaycode $IT)),*
+            ),
+            $crate::trace!(@stringify $($IT,)*),
+            $($crate::$unit($crate::__Quantity::__as_f64(&$IT) * $scale)),*
+        ));
+    };
+    (@x $($IT:expr),+ $(,)?) => {
+        $crate::trace!(@fmts("{:?}", "{:#x}") $($IT),+)
+    };
+    (@#depth($max:expr) $($IT:expr),* $(,)?) => {
+        $crate::__print_pretty(
+            format_args!(
+                $crate::trace!(@#fmt $($IT),*),
+                $crate::trace!(@stringify $($IT,)*),
+                $($crate::__value(&$IT)),*
+            ),
+            None,
+            Some($max),
         );
     };
-    ($($IT:expr),* $(,)?) => {
-        println!(
-            trace!(@fmt $($IT),*),
-            $(&$IT),*
+    (@#$width:literal $($IT:expr),* $(,)?) => {
+        $crate::__print_pretty(
+            format_args!(
+                $crate::trace!(@#fmt $($IT),*),
+                $crate::trace!(@stringify $($IT,)*),
+                $($crate::__value(&$IT)),*
+            ),
+            Some($width),
+            None,
+        );
+    };
+    (#$($IT:expr),* $(,)?) => {
+        $crate::__print_pretty(
+            format_args!(
+                $crate::trace!(@#fmt $($IT),*),
+                $crate::trace!(@stringify $($IT,)*),
+                $($crate::__value(&$IT)),*
+            ),
+            None,
+            None,
         );
     };
     (@line) => {
@@ -105,16 +938,14 @@ macro_rules! trace {
     };
     (@#fmt $($IT:expr),*) => {
         concat!(
-            trace!(@stringify $($IT,)*),
-            ":",
-            $(trace!(@#fmtcode $IT)),*
+            "{}:",
+            $($crate::trace!(@#fmtcode $IT)),*
         )
     };
     (@fmt $($IT:expr),*) => {
         concat!(
-            trace!(@stringify $($IT,)*),
-            ":",
-            $(trace!(@fmtcode $IT)),*
+            "{}:",
+            $($crate::trace!(@fmtcode $IT)),*
         )
     };
     (@#fmtcode $_:expr) => {
@@ -123,61 +954,4425 @@ macro_rules! trace {
     (@fmtcode $_:expr) => {
         " {:?}"
     };
-    (@stringify $HEAD:expr, $($IT:expr,)*) => {
-        concat!(
-            stringify!($HEAD),
-            $(
-                ", ",
+    (@speccode) => {
+        " {:?}"
+    };
+    (@speccode $spec:literal) => {
+        concat!(" {", $spec, "}")
+    };
+    (@specvalue $IT:expr) => {
+        $crate::__value(&$IT)
+    };
+    (@specvalue $IT:expr => $spec:literal) => {
+        &$IT
+    };
+    (@displaycode $_:expr) => {
+        " {}"
+    };
+    (@fmtsvalue ($($code:literal),+ $(,)?) $IT:expr) => {
+        $crate::__Formats(&$IT, &[$(|value, f| write!(f, $code, value)),+])
+    };
+    (@addrnames $HEAD:expr, $($IT:expr,)*) => {
+        concat!(
+            "&",
+            stringify!($HEAD),
+            $(
+                ", &",
+                stringify!($IT),
+            )*
+        )
+    };
+    (@addrcode $_:expr) => {
+        " {:p}"
+    };
+    (@csvfmt $HEAD:expr, $($IT:expr,)*) => {
+        concat!(
+            "{}",
+            $($crate::trace!(@csvcode $IT)),*
+        )
+    };
+    (@csvcode $_:expr) => {
+        ",{}"
+    };
+    (@stringify $HEAD:expr, $($IT:expr,)*) => {
+        concat!(
+            stringify!($HEAD),
+            $(
+                ", ",
                 stringify!($IT),
             )*
         )
     };
+    (@args [$({($name:expr) ($IT:expr) $($spec:literal)?})+]) => {
+        $crate::__print(format_args!(
+            concat!(
+                "{}:",
+                $($crate::trace!(@speccode $($spec)?)),*
+            ),
+            $crate::trace!(@names $($name,)+),
+            $($crate::trace!(@specvalue $IT $(=> $spec)?)),*
+        ));
+    };
+    (@args [$($done:tt)*] $name:tt = $IT:expr $(=> $spec:literal)? $(, $($rest:tt)*)?) => {
+        $crate::trace!(@args [$($done)* {($crate::trace!(@name $name)) ($IT) $($spec)?}] $($($rest)*)?)
+    };
+    (@name $name:literal) => {
+        $name
+    };
+    (@args [$($done:tt)*] $IT:expr $(=> $spec:literal)? $(, $($rest:tt)*)?) => {
+        $crate::trace!(@args [$($done)* {(stringify!($IT)) ($IT) $($spec)?}] $($($rest)*)?)
+    };
+    (@names $HEAD:expr, $($name:expr,)*) => {
+        concat!(
+            $HEAD,
+            $(
+                ", ",
+                $name,
+            )*
+        )
+    };
+    ($($IT:tt)+) => {
+        $crate::trace!(@args [] $($IT)+)
+    };
+}
+/// Installs a panic hook that traces the given variables.
+///
+/// The variables are moved into the hook, so this shows their values as of installation; share
+/// them through an `Arc` of something with interior mutability to see the last known values.
+/// See [`on_panic`].
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// use std::sync::Arc;
+/// use std::sync::atomic::AtomicUsize;
+/// let counter = Arc::new(AtomicUsize::new(0));
+/// let state = "starting";
+/// trace_panic_hook!(state, counter);
+/// ```
+#[macro_export]
+macro_rules! trace_panic_hook {
+    ($($IT:tt)*) => {
+        $crate::on_panic(move || $crate::trace!($($IT)*))
+    };
+}
+
+/// Times a block, indenting any traces inside it.
+///
+/// Prints a header on the way in and the elapsed time on the way out, even when leaving by
+/// `return`, `?`, `break`, or a panic. Evaluates to the block's value.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// let frame = trace_group!("render"; {
+///     let width = 80;
+///     trace!(width);
+///     width * 2
+/// });
+/// assert_eq!(frame, 160);
+/// // ── render ──
+/// //   width: 80
+/// // ── render: 3.2µs ──
+/// ```
+#[macro_export]
+macro_rules! trace_group {
+    ($label:expr; $body:block) => {{
+        let _group = $crate::__Group::new($label);
+        $body
+    }};
+}
+
+/// Traces entering the current function, and indents everything traced until it returns.
+///
+/// Put it at the top of a function. It prints `→` and the function's path, and when the
+/// function is left, by returning or by panicking, it prints `←` and the path again and takes
+/// the indentation back off. Instrumenting a few functions turns their traces into a call
+/// tree. Any arguments are traced right after the `→` line.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// fn fib(n: u32) -> u32 {
+///     trace_fn_enter!(n);
+///     if n < 2 { n } else { fib(n - 1) + fib(n - 2) }
+/// }
+/// fib(2);
+/// // → rust_out::main::fib
+/// //   n: 2
+/// //   → rust_out::main::fib
+/// //     n: 1
+/// //   ← rust_out::main::fib
+/// //   → rust_out::main::fib
+/// //     n: 0
+/// //   ← rust_out::main::fib
+/// // ← rust_out::main::fib
+/// ```
+///
+/// The indentation is the same as [`trace_group!`]'s, and is kept per thread.
+#[macro_export]
+macro_rules! trace_fn_enter {
+    ($($IT:tt)*) => {
+        let _trace_fn = $crate::__FnScope::enter({
+            fn f() {}
+            $crate::__fn_path(f)
+        });
+        $crate::__trace_fn_args!($($IT)*);
+    };
+}
+
+#[cfg(feature = "proc-macro")]
+extern crate eztrace_macros;
+
+#[cfg(feature = "tokio")]
+extern crate tokio;
+
+/// Traces a function's arguments each time it's called. Needs the `proc-macro` feature.
+///
+/// The output is the same as calling `trace!` on the arguments at the top of the function.
+/// `self` is included, in whatever form the function takes it. Arguments that are
+/// destructured, or whose names start with `_`, are left out; the rest have to be `Debug`.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// use eztrace::trace_args;
+///
+/// #[derive(Debug)]
+/// struct Account {
+///     balance: i64,
+/// }
+///
+/// impl Account {
+///     #[trace_args]
+///     fn withdraw(&mut self, amount: i64, _reason: &str) {
+///         self.balance -= amount;
+///     }
+/// }
+///
+/// Account { balance: 100 }.withdraw(30, "rent");
+/// // self, amount: Account { balance: 100 } 30
+/// ```
+///
+/// The expansion names `::eztrace::trace!`, so on edition 2015 the crate root needs its
+/// `extern crate eztrace;`.
+#[cfg(feature = "proc-macro")]
+pub use eztrace_macros::trace_args;
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __trace_fn_args {
+    () => {};
+    ($($IT:tt)+) => {
+        $crate::trace!($($IT)+);
+    };
+}
+
+/// Traces a value along with whether it's inside a range, without panicking if it isn't.
+///
+/// Evaluates to `true` if the value is in range. The value is borrowed, and the range is
+/// evaluated once. Anything implementing `RangeBounds`, like `a..b` or `a..=b`, will do.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// let (x, y) = (42, 150);
+/// trace_range!(x, 0..100);
+/// // x: 42 (in 0..100)
+/// if !trace_range!(y, 0..=100) {
+///     // y: 150 (OUT OF RANGE 0..=100)
+/// }
+/// ```
+#[macro_export]
+macro_rules! trace_range {
+    ($IT:expr, $range:expr $(,)?) => {
+        $crate::__trace_range(stringify!($IT), &$IT, &$range)
+    };
+}
+
+/// Prints two collections side by side, one element per row, marking the rows that differ.
+///
+/// Both are borrowed and iterated up to the longer length; the shorter one is padded with `—`.
+/// Elements are compared by their `{:?}` output, so they don't need to implement `PartialEq`
+/// with each other.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// let actual = vec![1, 2, 3];
+/// let expected = [1, 5];
+/// trace_side!(actual, expected);
+/// //   actual | expected
+/// //   1      | 1
+/// // ≠ 2      | 5
+/// // ≠ 3      | —
+/// ```
+#[macro_export]
+macro_rules! trace_side {
+    ($left:expr, $right:expr $(,)?) => {
+        $crate::__trace_side((stringify!($left), &$left), (stringify!($right), &$right))
+    };
+}
+
+/// Prints a map's entries sorted by key, one `key: value` per line, so the output is the same
+/// from run to run.
+///
+/// The map is borrowed; anything whose reference iterates over `(&K, &V)` pairs works, like
+/// `HashMap` and `BTreeMap`. Keys must be `Ord`; there's no sensible order to fall back on for
+/// a `HashMap`, so other keys are a compile error.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// use std::collections::HashMap;
+/// let mut scores = HashMap::new();
+/// scores.insert("carol", 7);
+/// scores.insert("alice", 12);
+/// scores.insert("bob", 3);
+/// trace_map!(scores);
+/// // scores:
+/// //   "alice": 12
+/// //   "bob": 3
+/// //   "carol": 7
+/// ```
+#[macro_export]
+macro_rules! trace_map {
+    ($map:expr $(,)?) => {
+        $crate::__trace_map(stringify!($map), &$map)
+    };
+}
+
+/// Traces a condition and evaluates to it, to see how each part of an `if` came out.
+///
+/// Each `trace_bool!` is just an expression, so `&&` and `||` still short-circuit past the
+/// ones that don't need evaluating, and those print nothing.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// let (a, b, c) = (1, 2, vec![3]);
+/// if trace_bool!(a < b) && trace_bool!(c.is_empty()) && trace_bool!(c[0] == 3) {
+///     unreachable!();
+/// }
+/// // a < b: true
+/// // c.is_empty(): false
+/// ```
+#[macro_export]
+macro_rules! trace_bool {
+    ($cond:expr $(,)?) => {
+        match $cond {
+            cond => {
+                let cond: bool = cond;
+                $crate::__print(format_args!("{}: {}", stringify!($cond), cond));
+                cond
+            },
+        }
+    };
+}
+
+/// Times how long a block or expression takes, and evaluates to its value.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// let total = trace_time!("phase", {
+///     let squares: Vec<u64> = (0..1000).map(|x| x * x).collect();
+///     squares.iter().sum::<u64>()
+/// });
+/// // phase: took 21.3µs
+/// assert_eq!(total, 332833500);
+/// ```
+///
+/// The time is printed when the block is left, however that happens, so `?`, `return`, and
+/// `break` inside it work as usual; you'll see the time up to that point.
+#[macro_export]
+macro_rules! trace_time {
+    ($label:expr, $body:expr $(,)?) => {{
+        let _timer = $crate::__Timer::new($label);
+        $body
+    }};
+}
+
+/// Compares two values and traces the result, without panicking like `assert_eq!` would.
+///
+/// Evaluates to the `bool` result, so you can branch on it. Both sides are borrowed.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// let actual = vec![1, 2, 3];
+/// let expected = vec![1, 2, 4];
+/// if !trace_cmp!(actual, expected) {
+///     // actual == expected? NO
+///     //   actual: [1, 2, 3]
+///     //   expected: [1, 2, 4]
+/// }
+/// assert!(trace_cmp!(actual.len(), 3));
+/// // actual.len() == 3? YES: 3
+/// ```
+#[macro_export]
+macro_rules! trace_cmp {
+    ($left:expr, $right:expr $(,)?) => {
+        match (&$left, &$right) {
+            (left, right) => {
+                let equal = *left == *right;
+                if equal {
+                    $crate::__print(format_args!(
+                        "{} == {}? YES: {:?}",
+                        stringify!($left), stringify!($right), $crate::__value(left),
+                    ));
+                } else {
+                    $crate::__print(format_args!(
+                        "{} == {}? NO\n  {}: {:?}\n  {}: {:?}",
+                        stringify!($left), stringify!($right),
+                        stringify!($left), $crate::__value(left),
+                        stringify!($right), $crate::__value(right),
+                    ));
+                }
+                equal
+            },
+        }
+    };
+}
+
+/// Wraps a variable in a [`TraceDrop`], which traces it when it's dropped.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// let outer = trace_drop!(String::from("outer"));
+/// {
+///     let inner = trace_drop!(vec![1, 2]);
+///     assert_eq!(inner.len(), 2);
+/// }
+/// // example.rs:4: dropping vec![1, 2]: [1, 2]
+/// drop(outer);
+/// // example.rs:2: dropping String::from("outer"): "outer"
+/// ```
+#[macro_export]
+macro_rules! trace_drop {
+    ($IT:expr $(,)?) => {
+        $crate::TraceDrop::new(stringify!($IT), $IT)
+    };
+}
+
+use std::any;
+use std::cell::{Cell, RefCell, UnsafeCell};
+use std::cmp;
+use std::collections::{HashSet, VecDeque};
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, IsTerminal, Write};
+use std::mem;
+use std::ops::{self, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::panic;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::mpsc::SyncSender;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+static PRETTY_INDENT: AtomicUsize = AtomicUsize::new(4);
+
+/// Sets how many spaces each nesting level gets in `trace!(#...)` output. Defaults to 4.
+pub fn set_pretty_indent(width: usize) {
+    PRETTY_INDENT.store(width, Ordering::Relaxed);
+}
+
+static MAX_DEPTH: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Sets how many levels of nesting `trace!(#...)` shows before cutting the rest to `…`, like
+/// `trace!(@#depth(N) ...)` does for one call. The default, `usize::MAX`, shows everything.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// eztrace::set_max_depth(0);
+/// let nested = vec![vec![1, 2], vec![3]];
+/// trace!(#nested);
+/// // nested: [
+/// //     …
+/// // ]
+/// # eztrace::set_max_depth(usize::MAX);
+/// ```
+pub fn set_max_depth(depth: usize) {
+    MAX_DEPTH.store(depth, Ordering::Relaxed);
+}
+
+/// Calls `dump` whenever a panic occurs.
+///
+/// The hook that was installed before (by default the one that prints the panic message) still
+/// runs first, then `dump`, so several calls stack up instead of replacing each other.
+///
+/// A panic from inside a trace, like one from a traced value's `Debug` impl, happens with the
+/// output locked. `dump` is skipped for those, since any trace it made would wait forever.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// use std::sync::Arc;
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// let counter = Arc::new(AtomicUsize::new(0));
+/// let c = counter.clone();
+/// eztrace::on_panic(move || trace!(c.load(Ordering::SeqCst)));
+/// ```
+pub fn on_panic<F>(dump: F)
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        previous(info);
+        if !EMITTING.with(Cell::get) {
+            dump();
+        }
+    }));
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(0);
+
+thread_local! {
+    static LEVEL_OVERRIDE: Cell<Option<u8>> = const { Cell::new(None) };
+}
+
+/// Sets the verbosity threshold for `trace!(@level(N) ...)`. Starts at 0.
+pub fn set_level(level: u8) {
+    LEVEL.store(level, Ordering::Relaxed);
+}
+
+/// The current verbosity threshold on this thread.
+pub fn level() -> u8 {
+    LEVEL_OVERRIDE
+        .with(Cell::get)
+        .unwrap_or_else(|| LEVEL.load(Ordering::Relaxed))
+}
+
+/// Runs `f` with the verbosity threshold on this thread set to `level`.
+///
+/// The previous threshold is restored afterwards, even if `f` panics. Nested calls work, and
+/// [`set_level`] doesn't affect threads that are inside `with_level`.
+pub fn with_level<T, F: FnOnce() -> T>(level: u8, f: F) -> T {
+    struct Restore(Option<u8>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            LEVEL_OVERRIDE.with(|cell| cell.set(self.0));
+        }
+    }
+    let _restore = Restore(LEVEL_OVERRIDE.with(|cell| cell.replace(Some(level))));
+    f()
+}
+
+#[doc(hidden)]
+pub fn __level_enabled(level: u8) -> bool {
+    level <= self::level()
+}
+
+static SHOW_LOCATION: AtomicBool = AtomicBool::new(true);
+
+/// Controls whether bare `trace!()` and `trace!(#)` print their location. On by default.
+///
+/// When off, those calls print nothing, though they still finish a line of `@tick` dots.
+/// Forms that exist to show where they are, like
+/// `trace!(@here "...")` and `trace!(@ret ...)`, keep their location regardless.
+pub fn set_show_location(show: bool) {
+    SHOW_LOCATION.store(show, Ordering::Relaxed);
+}
+
+static HERE_MARKER: Mutex<Option<String>> = Mutex::new(None);
+
+/// Changes what bare `trace!()` and `trace!(#)` print. Any `{location}` in `marker` is
+/// replaced with the usual file & line, plus the column for `trace!(#)`.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// eztrace::set_here_marker("• here");
+/// trace!();
+/// // • here
+/// eztrace::set_here_marker("• {location}");
+/// trace!();
+/// // • eztrace.rs:1
+/// eztrace::reset_here_marker();
+/// ```
+///
+/// [`set_show_location(false)`](set_show_location) still silences these entirely.
+pub fn set_here_marker(marker: &str) {
+    *lock(&HERE_MARKER) = Some(marker.to_owned());
+}
+
+/// Goes back to bare `trace!()` printing its file & line.
+pub fn reset_here_marker() {
+    *lock(&HERE_MARKER) = None;
+}
+
+#[doc(hidden)]
+pub fn __here(location: &'static str) {
+    if !SHOW_LOCATION.load(Ordering::Relaxed) {
+        end_ticks();
+        return;
+    }
+    if let Some(realtime) = Realtime::get() {
+        match realtime.here_marker {
+            Some(ref marker) => __print(format_args!("{}", Here(marker, location))),
+            None => __print(format_args!("{}", location)),
+        }
+        return;
+    }
+    let marker = lock(&HERE_MARKER).as_ref().map(|m| m.replace("{location}", location));
+    match marker {
+        Some(marker) => __print(format_args!("{}", marker)),
+        None => __print(format_args!("{}", location)),
+    }
+}
+
+/// A here-marker with `{location}` filled in as it's written, without allocating.
+struct Here<'a>(&'a str, &'static str);
+
+impl<'a> fmt::Display for Here<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, part) in self.0.split("{location}").enumerate() {
+            if i != 0 {
+                f.write_str(self.1)?;
+            }
+            f.write_str(part)?;
+        }
+        Ok(())
+    }
+}
+
+static SINK: Mutex<Option<Box<dyn Write + Send>>> = Mutex::new(None);
+static CRLF: AtomicBool = AtomicBool::new(cfg!(windows));
+
+/// Line terminator used when writing to a sink set with [`set_sink`] or [`add_tee`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\n`, the default everywhere except Windows.
+    Lf,
+    /// `\r\n`, the default on Windows.
+    CrLf,
+}
+
+/// Sends trace output to `sink` instead of stdout. Each line is flushed as it's written.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// eztrace::set_sink(std::io::stderr());
+/// trace!("this goes to stderr");
+/// eztrace::reset_sink();
+/// ```
+///
+/// The line is handed to the sink's `write_fmt` as it's formatted, so it's written in pieces;
+/// wrap an unbuffered sink like a `File` in a `BufWriter` to have it come out in one go.
+///
+/// # Allocation
+///
+/// Ordinary traces, to stdout or to a sink, don't allocate: the line is formatted straight into
+/// the writer. These do build the line in a `String` first:
+///
+/// - [`enable_ring`], which keeps a copy of every line.
+/// - [`set_prefix_fn`], whose function returns a `String` for each call, and [`set_tag`].
+/// - Traces inside a tokio task, with the `tokio` feature, for their `[task-N]`.
+/// - [`set_line_filter`], which needs the text to look at.
+/// - [`set_sanitize`], which does its escaping on the finished line.
+/// - [`set_channel`], which has to send each line as a `String`.
+/// - `trace!(#...)` with an indent other than the default 4, which has to rewrite the output.
+/// - `trace!(@distinct ...)`, which remembers what it has seen, `trace!(@csv ...)`, which checks
+///   each field for quoting, and `trace_side!`.
+///
+/// `benches/alloc.rs` counts allocations and times each of these; run it with `cargo bench`.
+pub fn set_sink<W: Write + Send + 'static>(sink: W) {
+    let mut current = lock(&SINK);
+    lock(&BATCH).flush(&mut current);
+    *current = Some(Box::new(sink));
+    *lock(&SYNC_FILE) = None;
+}
+
+/// Goes back to printing trace output to stdout.
+pub fn reset_sink() {
+    let mut current = lock(&SINK);
+    lock(&BATCH).flush(&mut current);
+    *current = None;
+    *lock(&SYNC_FILE) = None;
+}
+
+static SYNC_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+/// Sends trace output to a file, like [`set_sink`], and has [`barrier`] sync it to disk.
+///
+/// Fails only if the file's handle can't be duplicated, in which case the sink is unchanged.
+///
+/// ```no_run
+/// # #[macro_use] extern crate eztrace;
+/// use std::fs::OpenOptions;
+/// let log = OpenOptions::new().create(true).append(true).open("trace.log").unwrap();
+/// eztrace::set_file_sink(log).unwrap();
+/// ```
+pub fn set_file_sink(file: File) -> io::Result<()> {
+    let sync = file.try_clone()?;
+    let mut current = lock(&SINK);
+    lock(&BATCH).flush(&mut current);
+    *current = Some(Box::new(file));
+    *lock(&SYNC_FILE) = Some(sync);
+    Ok(())
+}
+
+/// Gets everything traced so far out of this process, for when another process is about to
+/// write to the same place and its output has to come after ours.
+///
+/// When this returns, output from every earlier trace, on any thread, has been handed to the
+/// OS: batches held back by [`FlushPolicy::Batched`] are written, and stdout, the sink and every
+/// tee are flushed. A file set with [`set_file_sink`] is then synced with `File::sync_data`.
+/// Traces that race with the call, on other threads, may land on either side of it.
+///
+/// ```no_run
+/// # #[macro_use] extern crate eztrace;
+/// use std::fs::OpenOptions;
+/// use std::process::Command;
+/// let log = OpenOptions::new().create(true).append(true).open("trace.log").unwrap();
+/// eztrace::set_file_sink(log).unwrap();
+/// trace!("spawning the worker");
+/// eztrace::barrier().unwrap();
+/// // The worker's own traces to trace.log come after ours.
+/// Command::new("./worker").status().unwrap();
+/// ```
+///
+/// Some things to know:
+///
+/// - Processes sharing a file should open it with `append(true)`. Otherwise each writes at its
+///   own offset, ordered or not, and they overwrite each other.
+/// - Once written, output is visible to other processes straight away; the sync is only for
+///   surviving a crash of the whole machine. On macOS `sync_data` doesn't flush the drive's own
+///   cache.
+/// - A sink or tee that buffers internally, like a `BufWriter`, is flushed, but whatever it
+///   wraps is only as far along as its own `flush` takes it. Only files from [`set_file_sink`]
+///   are synced.
+/// - Output sent down a [`set_channel`] channel, or waiting in a realtime buffer, is still in
+///   this process; draining it is up to you.
+///
+/// Returns the first error, after trying everything anyway.
+pub fn barrier() -> io::Result<()> {
+    // Holding the sink throughout keeps other threads' traces from slipping in half-done.
+    let mut sink = lock(&SINK);
+    lock(&BATCH).flush(&mut sink);
+    let mut result = match *sink {
+        Some(ref mut sink) => sink.flush(),
+        None => io::stdout().flush(),
+    };
+    for tee in lock(&TEES).iter_mut() {
+        result = result.and(tee.out.flush());
+    }
+    if let Some(ref file) = *lock(&SYNC_FILE) {
+        result = result.and(file.sync_data());
+    }
+    result
+}
+
+/// When output to stdout or a sink gets flushed. See [`set_flush_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// Write and flush each line as it's traced. The default.
+    EveryLine,
+    /// Hold output back, and write it out once `lines` lines are waiting, or once the oldest
+    /// has waited for `interval`, whichever comes first.
+    Batched {
+        lines: usize,
+        interval: Duration,
+    },
+}
+
+static BATCH: Mutex<Batch> = Mutex::new(Batch {
+    policy: FlushPolicy::EveryLine,
+    pending: Vec::new(),
+    lines: 0,
+    since: None,
+});
+
+/// Chooses between seeing each line straight away and not paying for a flush per line.
+///
+/// Flushing every line, as [`FlushPolicy::EveryLine`] does, is what you want when the program
+/// might crash, but a trace in a hot loop to a file or pipe can spend most of its time there.
+/// [`FlushPolicy::Batched`] collects output in memory and writes it all at once instead.
+///
+/// Nothing runs in the background: a batch is only written out when a trace finds it's due,
+/// by line count or by age. Output left waiting when tracing stops, or when the program
+/// exits, is lost unless you call [`flush`]; a panic or a `process::exit` won't do it for you.
+/// Changing the policy, or the sink, writes out what's waiting first. The ring buffer,
+/// channel and tees aren't batched.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// use std::time::Duration;
+/// use eztrace::FlushPolicy;
+/// eztrace::set_flush_policy(FlushPolicy::Batched {
+///     lines: 1000,
+///     interval: Duration::from_millis(100),
+/// });
+/// for i in 0..10_000 {
+///     trace!(i);
+/// }
+/// eztrace::flush();
+/// # eztrace::set_flush_policy(FlushPolicy::EveryLine);
+/// ```
+pub fn set_flush_policy(policy: FlushPolicy) {
+    let mut sink = lock(&SINK);
+    let mut batch = lock(&BATCH);
+    batch.flush(&mut sink);
+    batch.policy = policy;
 }
 
+/// Writes out any output held back by [`FlushPolicy::Batched`], and flushes stdout or the sink.
+pub fn flush() {
+    let mut sink = lock(&SINK);
+    lock(&BATCH).flush(&mut sink);
+    let _ = match *sink {
+        Some(ref mut sink) => sink.flush(),
+        None => io::stdout().flush(),
+    };
+}
+
+/// Output waiting to be written, under [`FlushPolicy::Batched`].
+struct Batch {
+    policy: FlushPolicy,
+    pending: Vec<u8>,
+    lines: usize,
+    /// When the oldest pending output was traced.
+    since: Option<Instant>,
+}
+
+impl Batch {
+    /// Adds `text` to the batch, writing the batch out if it's due. Returns `false`, having
+    /// done nothing, if output isn't being batched.
+    fn write(
+        &mut self,
+        sink: &mut Option<Box<dyn Write + Send>>,
+        text: &dyn fmt::Display,
+        newline: bool,
+    ) -> bool {
+        let (lines, interval) = match self.policy {
+            FlushPolicy::EveryLine => return false,
+            FlushPolicy::Batched { lines, interval } => (lines, interval),
+        };
+        let since = *self.since.get_or_insert_with(Instant::now);
+        let mut out = Endings {
+            out: &mut self.pending,
+            crlf: sink.is_some() && CRLF.load(Ordering::Relaxed),
+        };
+        let _ = write!(out, "{}", text);
+        if newline {
+            self.lines += 1;
+        }
+        if self.lines >= lines || since.elapsed() >= interval {
+            self.flush(sink);
+        }
+        true
+    }
+
+    fn flush(&mut self, sink: &mut Option<Box<dyn Write + Send>>) {
+        if self.pending.is_empty() {
+            return;
+        }
+        match *sink {
+            Some(ref mut sink) => {
+                let _ = sink.write_all(&self.pending).and_then(|()| sink.flush());
+            },
+            None => to_stdout(&String::from_utf8_lossy(&self.pending), false),
+        }
+        self.pending.clear();
+        self.lines = 0;
+        self.since = None;
+    }
+}
+
+/// Sets the line terminator used for sinks.
+///
+/// Output to stdout goes through `print!`, which always uses `\n`; this only affects sinks
+/// set with [`set_sink`] and tees added with [`add_tee`]. Newlines inside multi-line output, like `trace!(#...)`, are
+/// translated too.
+pub fn set_line_ending(ending: LineEnding) {
+    CRLF.store(ending == LineEnding::CrLf, Ordering::Relaxed);
+}
+
+/// Locks `mutex`, carrying on if a panic poisoned it; tracing shouldn't cascade failures.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Writes to the inner writer, turning `\n` into `\r\n` if asked to.
+struct Endings<'a> {
+    out: &'a mut dyn Write,
+    crlf: bool,
+}
+
+impl<'a> Write for Endings<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.crlf {
+            return self.out.write(buf);
+        }
+        for (i, chunk) in buf.split(|&b| b == b'\n').enumerate() {
+            if i != 0 {
+                self.out.write_all(b"\r\n")?;
+            }
+            self.out.write_all(chunk)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[doc(hidden)]
+pub fn __print(args: fmt::Arguments) {
+    emit(args, true);
+}
+
+/// Prints without ending the line, for `trace!(@tick)`.
+#[doc(hidden)]
+pub fn __print_partial(args: fmt::Arguments) {
+    emit(args, false);
+}
+
+/// Set while a line of ticks is waiting for its newline.
+static MID_LINE: AtomicBool = AtomicBool::new(false);
+
+/// Finishes a line of ticks, printing nothing else.
+fn end_ticks() {
+    if MID_LINE.load(Ordering::Relaxed) {
+        // Continues the line like another tick would, then marks it finished.
+        emit(format_args!("\n"), false);
+        MID_LINE.store(false, Ordering::Relaxed);
+    }
+}
+
+thread_local! {
+    /// Set while this thread is writing out a trace, and so holding the output locks.
+    static EMITTING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Marks this thread as writing out a trace until it's dropped, panics included.
+struct Emitting;
+
+impl Emitting {
+    /// Returns `None` if this thread is already writing one.
+    fn start() -> Option<Emitting> {
+        if EMITTING.with(|emitting| emitting.replace(true)) {
+            None
+        } else {
+            Some(Emitting)
+        }
+    }
+}
+
+impl Drop for Emitting {
+    fn drop(&mut self) {
+        EMITTING.with(|emitting| emitting.set(false));
+    }
+}
+
+fn emit(args: fmt::Arguments, newline: bool) {
+    if let Some(realtime) = Realtime::get() {
+        realtime.push(args);
+        return;
+    }
+    // Values are formatted with the output locked, so a trace from inside one of their
+    // `Debug` impls would deadlock. Drop it instead.
+    let _emitting = match Emitting::start() {
+        Some(emitting) => emitting,
+        None => return,
+    };
+    let filter = lock(&LINE_FILTER);
+    match *filter {
+        Some(ref filter) => {
+            // The text only decides; the record is rendered afresh, so tees can restyle it.
+            let text = args.to_string();
+            if panic::catch_unwind(panic::AssertUnwindSafe(|| filter(&text))).unwrap_or(true) {
+                write_record(args, newline);
+            }
+        },
+        None => {
+            drop(filter);
+            write_record(args, newline);
+        },
+    }
+}
+
+fn write_record(args: fmt::Arguments, newline: bool) {
+    // Holding the sink for the whole call keeps other threads from cutting in.
+    let mut sink = lock(&SINK);
+    let was_mid_line = MID_LINE.swap(!newline, Ordering::Relaxed);
+    // More ticks on the same line don't get prefixed again.
+    let continued = !newline && was_mid_line;
+    let prefix = if continued { String::new() } else { line_prefix() };
+    let record = Record {
+        lead: if newline && was_mid_line { "\n" } else { "" },
+        prefix: &prefix,
+        indent: if continued { 0 } else { INDENT.with(Cell::get) },
+        args,
+        end: if newline { "\n" } else { "" },
+    };
+    let sanitize = SANITIZE.load(Ordering::Relaxed);
+    for tee in lock(&TEES).iter_mut() {
+        tee.write(&record, sanitize);
+    }
+    let mut ring = lock(&RING);
+    if ring.is_none() && !sanitize {
+        drop(ring);
+        deliver(&mut sink, &record, newline);
+        return;
+    }
+    let mut text = record.to_string();
+    if sanitize {
+        text = sanitized(&text);
+    }
+    if let Some(ref mut ring) = *ring {
+        ring.push(&text);
+    }
+    drop(ring);
+    deliver(&mut sink, &text, newline);
+}
+
+/// Writes out finished text, wherever it's meant to go.
+fn deliver(sink: &mut Option<Box<dyn Write + Send>>, text: &dyn fmt::Display, newline: bool) {
+    #[cfg(test)]
+    {
+        if tests::captured(format_args!("{}", text)) {
+            return;
+        }
+    }
+    if let Some(ref mut channel) = *lock(&CHANNEL) {
+        channel.send(&text.to_string());
+        return;
+    }
+    if lock(&BATCH).write(sink, text, newline) {
+        return;
+    }
+    match *sink {
+        Some(ref mut sink) => {
+            let mut out = Endings {
+                out: &mut **sink,
+                crlf: CRLF.load(Ordering::Relaxed),
+            };
+            // Nowhere to report a failed trace, so don't.
+            let _ = write!(out, "{}", text).and_then(|()| out.flush());
+        },
+        None => to_stdout(text, newline),
+    }
+}
+
+thread_local! {
+    /// Set while [`to_stdout`] is rendering, so [`__Link`] knows the text is going to stdout.
+    static TO_STDOUT: Cell<bool> = const { Cell::new(false) };
+}
+
+fn to_stdout(text: &dyn fmt::Display, newline: bool) {
+    struct Done;
+    impl Drop for Done {
+        fn drop(&mut self) {
+            TO_STDOUT.with(|to_stdout| to_stdout.set(false));
+        }
+    }
+    TO_STDOUT.with(|to_stdout| to_stdout.set(true));
+    let done = Done;
+    print!("{}", text);
+    drop(done);
+    if !newline {
+        let _ = io::stdout().flush();
+    }
+}
+
+static TEES: Mutex<Vec<Tee>> = Mutex::new(Vec::new());
+
+/// How a sink added with [`add_tee`] wants values laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TeeStyle {
+    /// Every value on one line, like `{:?}`, even from `trace!(#...)`.
+    Compact,
+    /// Every value spread out, like `{:#?}`, even from plain `trace!(...)`.
+    Pretty,
+}
+
+/// Sends a copy of all trace output to `sink`, with values restyled, as well as printing it
+/// as usual.
+///
+/// For watching terse output live while keeping the details in a file. Each call adds another
+/// sink; every one gets its own rendering of each trace, in its own style. Only values traced
+/// with `{:?}` or `{:#?}` are restyled: labels, locations, `@x` and `=> "..."` formats come
+/// out the same everywhere. Like [`set_sink`], each line is flushed as it's written.
+///
+/// ```no_run
+/// # #[macro_use] extern crate eztrace;
+/// # #[derive(Debug)] struct Config { port: u16 }
+/// let log = std::fs::File::create("trace.log").unwrap();
+/// eztrace::add_tee(log, eztrace::TeeStyle::Pretty);
+/// let config = Config { port: 80 };
+/// trace!(config);
+/// // stdout:    config: Config { port: 80 }
+/// // trace.log: config: Config {
+/// //                port: 80,
+/// //            }
+/// ```
+pub fn add_tee<W: Write + Send + 'static>(sink: W, style: TeeStyle) {
+    lock(&TEES).push(Tee {
+        out: Box::new(sink),
+        style,
+    });
+}
+
+/// Removes all the sinks added with [`add_tee`].
+pub fn clear_tees() {
+    lock(&TEES).clear();
+}
+
+struct Tee {
+    out: Box<dyn Write + Send>,
+    style: TeeStyle,
+}
+
+thread_local! {
+    /// Set while rendering output for a [`Tee`].
+    static STYLE: Cell<Option<TeeStyle>> = const { Cell::new(None) };
+}
+
+impl Tee {
+    fn write(&mut self, record: &Record, sanitize: bool) {
+        let tee_style = self.style;
+        let mut out = Endings {
+            out: &mut *self.out,
+            crlf: CRLF.load(Ordering::Relaxed),
+        };
+        STYLE.with(|style| style.set(Some(tee_style)));
+        let written = if sanitize {
+            write!(out, "{}", sanitized(&record.to_string()))
+        } else {
+            write!(out, "{}", record)
+        };
+        STYLE.with(|style| style.set(None));
+        let _ = written.and_then(|()| out.flush());
+    }
+}
+
+static SANITIZE: AtomicBool = AtomicBool::new(false);
+
+/// Escapes control characters in trace output, so that tracing untrusted data can't send
+/// the terminal escape sequences. Off by default.
+///
+/// `Debug` already escapes the inside of strings, but `Display` formats, custom `Debug` impls
+/// and [`TraceFormat`] might not. This works on the finished text, escaping everything except
+/// the line breaks between lines the Rust way (`\u{1b}`, `\r`, `\0`...). Like the ring buffer,
+/// it means building each line in a `String` before writing it.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// eztrace::set_sanitize(true);
+/// let name = "\x1b[2Jmallory";
+/// trace!(name => "");
+/// // name: \u{1b}[2Jmallory
+/// # eztrace::set_sanitize(false);
+/// ```
+pub fn set_sanitize(sanitize: bool) {
+    SANITIZE.store(sanitize, Ordering::Relaxed);
+}
+
+fn sanitized(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_control() && c != '\n' {
+            out.extend(c.escape_default());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+static RING: Mutex<Option<Ring>> = Mutex::new(None);
+
+/// Keeps the last `capacity` lines of trace output in memory, for [`dump_ring`].
+///
+/// Output is still written out as usual. Calling this again resizes the buffer and clears it.
+pub fn enable_ring(capacity: usize) {
+    *lock(&RING) = Some(Ring {
+        lines: VecDeque::with_capacity(capacity),
+        capacity,
+        open: false,
+    });
+}
+
+/// Stops recording output, throwing away anything recorded.
+pub fn disable_ring() {
+    *lock(&RING) = None;
+}
+
+/// Returns the most recent lines recorded since [`enable_ring`], oldest first.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// eztrace::enable_ring(100);
+/// for frame in 0..1000 {
+///     trace!(frame);
+/// }
+/// let recent = eztrace::dump_ring();
+/// assert_eq!(recent.len(), 100);
+/// assert_eq!(recent[99], "frame: 999");
+/// # eztrace::disable_ring();
+/// ```
+pub fn dump_ring() -> Vec<String> {
+    match *lock(&RING) {
+        Some(ref ring) => ring.lines.iter().cloned().collect(),
+        None => Vec::new(),
+    }
+}
+
+/// The last few lines of output.
+struct Ring {
+    lines: VecDeque<String>,
+    capacity: usize,
+    /// Set if the newest line hasn't been ended yet.
+    open: bool,
+}
+
+impl Ring {
+    fn push(&mut self, text: &str) {
+        if self.capacity == 0 {
+            return;
+        }
+        for piece in text.split_inclusive('\n') {
+            let line = piece.trim_end_matches('\n');
+            match self.lines.back_mut() {
+                Some(last) if self.open => last.push_str(line),
+                _ => {
+                    if self.lines.len() == self.capacity {
+                        self.lines.pop_front();
+                    }
+                    self.lines.push_back(line.to_string());
+                },
+            }
+            self.open = !piece.ends_with('\n');
+        }
+    }
+}
+
+/// How many bytes of each trace [`enable_realtime_buffer`] keeps.
+pub const REALTIME_LINE_BYTES: usize = 256;
+
+static REALTIME: AtomicPtr<Realtime> = AtomicPtr::new(ptr::null_mut());
+
+/// Records output into a fixed set of pre-allocated lines, for threads that can't allocate or
+/// block, like audio callbacks. Drain them from a thread that can with [`drain_realtime_buffer`].
+///
+/// While it's on, a trace is formatted on the stack, cut to [`REALTIME_LINE_BYTES`] (ending in
+/// `…` if it didn't fit), and copied into a free line without taking any locks. When all
+/// `capacity` lines are waiting to be drained, new traces are dropped and counted by
+/// [`realtime_dropped`], so a slow drain loses the newest output rather than stalling.
+///
+/// Everything else about output is skipped: the sink, tees, channel, ring, line filter,
+/// sanitizing, prefix, tag and indentation. The markers from [`set_here_marker`] and
+/// [`set_none_marker`] are the ones set when the buffer was turned on, so reading them needn't
+/// lock; later changes show up once it's turned on again. `@tick` dots become entries of their own. The trace path itself doesn't
+/// allocate for plain `trace!(...)`, but a value's own `Debug` impl might, and so do forms that
+/// remember values between calls, like `@distinct`, and forms that build strings, like `@stuck`.
+///
+/// This allocates `capacity` lines now, and they're never freed: [`disable_realtime_buffer`]
+/// can't know that no thread is still writing to them. Turn it on once, early.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// eztrace::enable_realtime_buffer(1024);
+/// // On the realtime thread:
+/// let sample = 0.25;
+/// trace!(sample);
+/// // Later, somewhere allowed to block:
+/// assert_eq!(eztrace::drain_realtime_buffer(), ["sample: 0.25"]);
+/// # eztrace::disable_realtime_buffer();
+/// ```
+pub fn enable_realtime_buffer(capacity: usize) {
+    let mut realtime = Box::new(Realtime::new(capacity));
+    realtime.here_marker = lock(&HERE_MARKER).clone();
+    realtime.none_marker = lock(&NONE_MARKER).clone();
+    REALTIME.store(Box::leak(realtime), Ordering::Release);
+}
+
+/// Goes back to printing as usual. Lines that haven't been drained are lost.
+pub fn disable_realtime_buffer() {
+    REALTIME.store(ptr::null_mut(), Ordering::Release);
+}
+
+/// Takes the lines recorded since the last drain, oldest first. Each is one trace, without its
+/// line ending; `trace!(#...)` entries span several lines.
+///
+/// This allocates, so call it from a thread that's allowed to.
+pub fn drain_realtime_buffer() -> Vec<String> {
+    let mut lines = Vec::new();
+    if let Some(realtime) = Realtime::get() {
+        while let Some(line) = realtime.pop() {
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+/// How many traces were dropped because the buffer was full, since [`enable_realtime_buffer`].
+pub fn realtime_dropped() -> usize {
+    Realtime::get().map_or(0, |realtime| realtime.dropped.load(Ordering::Relaxed))
+}
+
+/// A bounded queue of fixed-size lines that any number of threads can push to without locks.
+///
+/// Each slot's `seq` says whose turn it is: the slot for position `pos` is free to write when
+/// `seq == pos`, holds a line to read when `seq == pos + 1`, and is handed on to the writer of
+/// `pos + capacity` once read.
+struct Realtime {
+    slots: Box<[Slot]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    dropped: AtomicUsize,
+    /// Copied when the buffer is enabled, for reading without a lock.
+    here_marker: Option<String>,
+    none_marker: Option<String>,
+}
+
+struct Slot {
+    seq: AtomicUsize,
+    line: UnsafeCell<([u8; REALTIME_LINE_BYTES], usize)>,
+}
+
+// Slots are only touched by whoever won them through `seq` and `head` or `tail`.
+unsafe impl Sync for Realtime {}
+
+impl Realtime {
+    fn new(capacity: usize) -> Self {
+        Realtime {
+            slots: (0..capacity).map(|i| Slot {
+                seq: AtomicUsize::new(i),
+                line: UnsafeCell::new(([0; REALTIME_LINE_BYTES], 0)),
+            }).collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+            here_marker: None,
+            none_marker: None,
+        }
+    }
+
+    fn get() -> Option<&'static Realtime> {
+        // Buffers are leaked, so any we see lives forever.
+        unsafe { REALTIME.load(Ordering::Acquire).as_ref() }
+    }
+
+    fn push(&self, args: fmt::Arguments) {
+        let mut line = Fixed {
+            bytes: [0; REALTIME_LINE_BYTES],
+            len: 0,
+        };
+        // Running out of room is the only error, and `Fixed` has already marked it.
+        let _ = fmt::write(&mut line, args);
+        let capacity = self.slots.len();
+        if capacity == 0 {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let mut pos = self.head.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos % capacity];
+            let seq = slot.seq.load(Ordering::Acquire);
+            if seq == pos {
+                if let Err(actual) = self.head.compare_exchange_weak(pos, pos + 1, Ordering::Relaxed, Ordering::Relaxed) {
+                    pos = actual;
+                    continue;
+                }
+                unsafe {
+                    let stored = &mut *slot.line.get();
+                    stored.0[..line.len].copy_from_slice(&line.bytes[..line.len]);
+                    stored.1 = line.len;
+                }
+                slot.seq.store(pos + 1, Ordering::Release);
+                return;
+            } else if seq < pos {
+                // Still holding the line from a lap ago.
+                break;
+            } else {
+                pos = self.head.load(Ordering::Relaxed);
+            }
+        }
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn pop(&self) -> Option<String> {
+        let capacity = self.slots.len();
+        if capacity == 0 {
+            return None;
+        }
+        let mut pos = self.tail.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos % capacity];
+            let seq = slot.seq.load(Ordering::Acquire);
+            if seq == pos + 1 {
+                if let Err(actual) = self.tail.compare_exchange_weak(pos, pos + 1, Ordering::Relaxed, Ordering::Relaxed) {
+                    pos = actual;
+                    continue;
+                }
+                let line = unsafe {
+                    let stored = &*slot.line.get();
+                    String::from_utf8_lossy(&stored.0[..stored.1]).into_owned()
+                };
+                slot.seq.store(pos + capacity, Ordering::Release);
+                return Some(line);
+            } else if seq <= pos {
+                // Nothing written here yet.
+                return None;
+            } else {
+                pos = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Formats into a fixed buffer, cutting the text short with `…` if it doesn't fit.
+struct Fixed {
+    bytes: [u8; REALTIME_LINE_BYTES],
+    len: usize,
+}
+
+impl fmt::Write for Fixed {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = self.bytes.len() - self.len;
+        if s.len() <= room {
+            self.bytes[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+            self.len += s.len();
+            return Ok(());
+        }
+        let mut cut = room.min(s.len());
+        while !s.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        self.bytes[self.len..self.len + cut].copy_from_slice(&s.as_bytes()[..cut]);
+        self.len += cut;
+        // Back up far enough for the `…`, without splitting a character.
+        let ellipsis = "…".as_bytes();
+        self.len = cmp::min(self.len, self.bytes.len() - ellipsis.len());
+        while self.len > 0 && (self.bytes[self.len] & 0xc0) == 0x80 {
+            self.len -= 1;
+        }
+        self.bytes[self.len..self.len + ellipsis.len()].copy_from_slice(ellipsis);
+        self.len += ellipsis.len();
+        Err(fmt::Error)
+    }
+}
+
+static CHANNEL: Mutex<Option<Channel>> = Mutex::new(None);
+
+/// Sends trace output down a channel, one `String` per line, instead of printing it. For apps
+/// that own the screen and want to show traces in a panel of their own.
+///
+/// Sending never blocks: lines that don't fit in the channel are dropped. If the receiver goes
+/// away, output is dropped too, until [`clear_channel`] goes back to printing as usual. Lines
+/// don't include their `\n`, and a line of `trace!(@tick)` dots is sent once it's finished.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// use std::sync::mpsc;
+/// let (sender, receiver) = mpsc::sync_channel(1000);
+/// eztrace::set_channel(sender);
+/// let answer = 42;
+/// trace!(answer);
+/// assert_eq!(receiver.recv().unwrap(), "answer: 42");
+/// # eztrace::clear_channel();
+/// ```
+pub fn set_channel(sender: SyncSender<String>) {
+    *lock(&CHANNEL) = Some(Channel {
+        sender,
+        pending: String::new(),
+    });
+}
+
+/// Stops sending to the channel from [`set_channel`], and goes back to printing.
+pub fn clear_channel() {
+    *lock(&CHANNEL) = None;
+}
+
+struct Channel {
+    sender: SyncSender<String>,
+    /// The start of a line that hasn't been ended yet.
+    pending: String,
+}
+
+impl Channel {
+    fn send(&mut self, text: &str) {
+        self.pending.push_str(text);
+        while let Some(end) = self.pending.find('\n') {
+            let rest = self.pending.split_off(end + 1);
+            let mut line = mem::replace(&mut self.pending, rest);
+            line.pop();
+            // Full or disconnected, the line is lost either way.
+            let _ = self.sender.try_send(line);
+        }
+    }
+}
+
+type LineFilter = Box<dyn Fn(&str) -> bool + Send + Sync>;
+
+static LINE_FILTER: Mutex<Option<LineFilter>> = Mutex::new(None);
+
+/// Drops every trace that `filter` returns `false` for, to hide noise without editing the
+/// call sites.
+///
+/// `filter` sees what the trace itself prints, before anything is added in front of it: no
+/// [`set_tag`] tag, no [`set_prefix_fn`] prefix, no group indentation. It sees the whole
+/// trace at once, so for `trace!(#...)` that's several lines. Ticks from `trace!(@tick)` are
+/// filtered one at a time. If it panics, the trace is kept.
+///
+/// Each trace is formatted into a `String` for the filter to look at, and it's called on the
+/// tracing thread while holding a lock, so like the prefix function it should be quick and
+/// mustn't trace anything itself.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// eztrace::set_line_filter(Box::new(|line| !line.starts_with("frame")));
+/// for frame in 0..3 {
+///     trace!(frame);
+/// }
+/// trace!("done");
+/// // done
+/// # eztrace::clear_line_filter();
+/// ```
+pub fn set_line_filter(filter: Box<dyn Fn(&str) -> bool + Send + Sync>) {
+    *lock(&LINE_FILTER) = Some(filter);
+}
+
+/// Removes the filter set by [`set_line_filter`].
+pub fn clear_line_filter() {
+    *lock(&LINE_FILTER) = None;
+}
+
+type PrefixFn = Box<dyn Fn() -> String + Send + Sync>;
+
+static PREFIX_FN: Mutex<Option<PrefixFn>> = Mutex::new(None);
+
+/// Puts whatever `prefix` returns at the start of every line of trace output.
+///
+/// Use this for context `trace!` can't know about, like a request id kept in a thread-local.
+/// It's called once per trace, on the thread doing the tracing, while holding the lock that
+/// keeps output from different threads apart; so it should be quick, and it mustn't trace
+/// anything itself. If it panics, that trace goes out without a prefix.
+///
+/// The cost is one call and one `String` per trace, on top of the usual formatting.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// use std::cell::Cell;
+/// thread_local!(static REQUEST: Cell<u32> = Cell::new(0));
+/// eztrace::set_prefix_fn(Box::new(|| format!("[req {}] ", REQUEST.with(Cell::get))));
+/// REQUEST.with(|r| r.set(17));
+/// let user = "bob";
+/// trace!(user);
+/// // [req 17] user: "bob"
+/// # eztrace::clear_prefix_fn();
+/// ```
+///
+/// In async code, the thread says little about which task is running. With the `tokio`
+/// feature, lines traced inside a tokio task start with its id, from `tokio::task::try_id()`,
+/// before this prefix. It only works inside a task: lines traced anywhere else, including
+/// directly in a `block_on`, go untagged.
+///
+/// ```edition2018
+/// # #[macro_use] extern crate eztrace;
+/// # #[cfg(feature = "tokio")] {
+/// let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+/// runtime.block_on(async {
+///     tokio::spawn(async { trace!("in a task") }).await.unwrap();
+/// });
+/// // [task-1] in a task
+/// # }
+/// ```
+pub fn set_prefix_fn(prefix: Box<dyn Fn() -> String + Send + Sync>) {
+    *lock(&PREFIX_FN) = Some(prefix);
+}
+
+/// Removes the function set by [`set_prefix_fn`].
+pub fn clear_prefix_fn() {
+    *lock(&PREFIX_FN) = None;
+}
+
+static TAG: Mutex<Option<String>> = Mutex::new(None);
+
+/// Starts every line of trace output with `tag` and a `|`, so `grep` can pick them out from
+/// everything else in a log.
+///
+/// The tag comes first, then anything from [`set_prefix_fn`], then group indentation.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// eztrace::set_tag("EZTRACE");
+/// let x = 1;
+/// trace!(x);
+/// // EZTRACE|x: 1
+/// # eztrace::clear_tag();
+/// ```
+pub fn set_tag(tag: &str) {
+    *lock(&TAG) = Some(tag.to_owned());
+}
+
+/// Removes the tag set by [`set_tag`].
+pub fn clear_tag() {
+    *lock(&TAG) = None;
+}
+
+static DEPTH_MARKER: Mutex<Option<String>> = Mutex::new(None);
+
+/// Goes one level deeper in the call tree drawn by `trace!(@depth ...)`, on this thread.
+///
+/// Unlike [`trace_group!`], nothing is printed and nothing else is indented: the depth only
+/// shows up in `@depth` traces, as one `│ ` per level.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// fn visit(node: u32) {
+///     trace!(@depth node);
+///     eztrace::push_depth();
+///     if node < 4 {
+///         visit(node * 2);
+///         visit(node * 2 + 1);
+///     }
+///     eztrace::pop_depth();
+/// }
+/// visit(1);
+/// // node: 1
+/// // │ node: 2
+/// // │ │ node: 4
+/// // │ │ node: 5
+/// // │ node: 3
+/// // │ │ node: 6
+/// // │ │ node: 7
+/// ```
+///
+/// Every push needs a pop, even if the code in between panics or returns early; a
+/// [`DepthGuard`] takes care of that.
+pub fn push_depth() {
+    DEPTH.with(|depth| depth.set(depth.get() + 1));
+}
+
+/// Goes back up a level after [`push_depth`]. Popping at the top does nothing.
+pub fn pop_depth() {
+    DEPTH.with(|depth| depth.set(depth.get().saturating_sub(1)));
+}
+
+/// The depth `trace!(@depth ...)` draws on this thread.
+pub fn depth() -> usize {
+    DEPTH.with(Cell::get)
+}
+
+/// Goes a level deeper, like [`push_depth`], until it's dropped.
+///
+/// Dropping it puts the depth back to what it was when the guard was made, so an early
+/// return, a panic that unwinds through it, or a forgotten [`pop_depth`] inside can't leave
+/// the tree lopsided.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// fn parse(tokens: &[&str]) -> Result<usize, String> {
+///     let _depth = eztrace::DepthGuard::new();
+///     for (i, token) in tokens.iter().enumerate() {
+///         trace!(@depth token);
+///         if token.is_empty() {
+///             return Err(format!("empty token at {}", i));
+///         }
+///     }
+///     Ok(tokens.len())
+/// }
+/// assert!(parse(&["a", ""]).is_err());
+/// assert_eq!(eztrace::depth(), 0);
+/// ```
+pub struct DepthGuard {
+    restore: usize,
+}
+
+impl DepthGuard {
+    pub fn new() -> Self {
+        let restore = depth();
+        push_depth();
+        DepthGuard { restore }
+    }
+}
+
+impl Default for DepthGuard {
+    /// The same as [`DepthGuard::new`]: pushes a level.
+    fn default() -> Self {
+        DepthGuard::new()
+    }
+}
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        DEPTH.with(|depth| depth.set(self.restore));
+    }
+}
+
+/// Changes what `trace!(@depth ...)` draws for each level, which is `│ ` by default.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// eztrace::set_depth_marker(". ");
+/// let _depth = eztrace::DepthGuard::new();
+/// trace!(@depth "in");
+/// // . in
+/// # eztrace::set_depth_marker("│ ");
+/// ```
+pub fn set_depth_marker(marker: &str) {
+    *lock(&DEPTH_MARKER) = Some(marker.to_owned());
+}
+
+/// Draws the depth for the trace it's alive for.
+#[doc(hidden)]
+pub struct __DrawDepth(bool);
+
+impl __DrawDepth {
+    pub fn new() -> Self {
+        __DrawDepth(DRAW_DEPTH.with(|draw| draw.replace(true)))
+    }
+}
+
+impl Default for __DrawDepth {
+    fn default() -> Self {
+        __DrawDepth::new()
+    }
+}
+
+impl Drop for __DrawDepth {
+    fn drop(&mut self) {
+        DRAW_DEPTH.with(|draw| draw.set(self.0));
+    }
+}
+
+fn line_prefix() -> String {
+    let mut prefix = match *lock(&PREFIX_FN) {
+        Some(ref prefix) => {
+            panic::catch_unwind(panic::AssertUnwindSafe(prefix)).unwrap_or_default()
+        },
+        None => String::new(),
+    };
+    #[cfg(feature = "tokio")]
+    {
+        if let Some(id) = tokio::task::try_id() {
+            prefix = format!("[task-{}] {}", id, prefix);
+        }
+    }
+    if DRAW_DEPTH.with(Cell::get) {
+        let marker = lock(&DEPTH_MARKER);
+        prefix.push_str(&marker.as_deref().unwrap_or("│ ").repeat(depth()));
+    }
+    match *lock(&TAG) {
+        Some(ref tag) => format!("{}|{}", tag, prefix),
+        None => prefix,
+    }
+}
+
+/// One call's worth of output, laid out for writing.
+struct Record<'a> {
+    /// Ends a line of ticks left hanging.
+    lead: &'static str,
+    /// Goes at the start of every line.
+    prefix: &'a str,
+    /// Levels of indentation to put in front of every line, after the prefix.
+    indent: usize,
+    args: fmt::Arguments<'a>,
+    end: &'static str,
+}
+
+impl<'a> fmt::Display for Record<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.lead)?;
+        if self.prefix.is_empty() && self.indent == 0 {
+            f.write_fmt(self.args)?;
+        } else {
+            let mut out = Indented {
+                out: f,
+                prefix: self.prefix,
+                indent: self.indent,
+                line_start: true,
+            };
+            fmt::write(&mut out, self.args)?;
+        }
+        f.write_str(self.end)
+    }
+}
+
+/// Prefixes and indents every line written through it.
+struct Indented<'a, 'b: 'a> {
+    out: &'a mut fmt::Formatter<'b>,
+    prefix: &'a str,
+    indent: usize,
+    line_start: bool,
+}
+
+impl<'a, 'b> fmt::Write for Indented<'a, 'b> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for line in s.split_inclusive('\n') {
+            if self.line_start {
+                self.out.write_str(self.prefix)?;
+                for _ in 0..self.indent {
+                    self.out.write_str("  ")?;
+                }
+            }
+            self.out.write_str(line)?;
+            self.line_start = line.ends_with('\n');
+        }
+        Ok(())
+    }
+}
+
+thread_local! {
+    /// How many `trace_group!`s and `trace_fn_enter!`s this thread is inside.
+    static INDENT: Cell<usize> = const { Cell::new(0) };
+    /// Where this thread is in the tree drawn by `trace!(@depth ...)`.
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    /// Set while a `trace!(@depth ...)` is printing.
+    static DRAW_DEPTH: Cell<bool> = const { Cell::new(false) };
+}
+
+#[doc(hidden)]
+pub fn __trace_range<T, B, R>(name: &str, value: &T, range: &R) -> bool
+where
+    T: ?Sized + PartialOrd<B> + fmt::Debug,
+    B: PartialOrd<T>,
+    R: RangeBounds<B> + fmt::Debug,
+{
+    let inside = range.contains(value);
+    let verdict = if inside { "in" } else { "OUT OF RANGE" };
+    __print(format_args!("{}: {:?} ({} {:?})", name, __value(value), verdict, range));
+    inside
+}
+
+#[doc(hidden)]
+pub fn __trace_side<L, R>((left_name, left): (&str, L), (right_name, right): (&str, R))
+where
+    L: IntoIterator,
+    L::Item: fmt::Debug,
+    R: IntoIterator,
+    R::Item: fmt::Debug,
+{
+    let left: Vec<String> = left.into_iter().map(|v| format!("{:?}", __value(&v))).collect();
+    let right: Vec<String> = right.into_iter().map(|v| format!("{:?}", __value(&v))).collect();
+    let width = left.iter().map(|v| v.chars().count()).chain(Some(left_name.chars().count())).max();
+    let width = width.unwrap_or(0);
+    let mut out = format!("  {:width$} | {}", left_name, right_name, width = width);
+    for i in 0..left.len().max(right.len()) {
+        let l = left.get(i).map_or("—", |v| v);
+        let r = right.get(i).map_or("—", |v| v);
+        let marker = if left.get(i) == right.get(i) { ' ' } else { '≠' };
+        out.push_str(&format!("\n{} {:width$} | {}", marker, l, r, width = width));
+    }
+    __print(format_args!("{}", out));
+}
+
+/// A source location, as an OSC 8 hyperlink when the terminal is likely to show one.
+#[doc(hidden)]
+pub struct __Link {
+    file: &'static str,
+    line: u32,
+    base: Option<PathBuf>,
+}
+
+impl __Link {
+    pub fn new(file: &'static str, line: u32) -> Self {
+        // Decided up front: formatting happens while the output locks are held. Whether this
+        // rendering is headed for stdout is only known then, and is checked in `fmt`.
+        let no_color = matches!(env::var_os("NO_COLOR"), Some(ref v) if !v.is_empty());
+        let linkable = io::stdout().is_terminal() && !no_color;
+        let base = if linkable {
+            env::var_os("EZTRACE_SRC_DIR").map(PathBuf::from).or_else(|| env::current_dir().ok())
+        } else {
+            None
+        };
+        __Link { file, line, base }
+    }
+}
+
+impl fmt::Display for __Link {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.base {
+            Some(ref base) if TO_STDOUT.with(Cell::get) => write!(
+                f,
+                "\x1b]8;;{}\x1b\\{}:{}\x1b]8;;\x1b\\",
+                file_url(&base.join(self.file)),
+                self.file,
+                self.line,
+            ),
+            _ => write!(f, "{}:{}", self.file, self.line),
+        }
+    }
+}
+
+fn file_url(path: &Path) -> String {
+    let mut url = String::from("file://");
+    let path = path.to_string_lossy().replace('\\', "/");
+    if !path.starts_with('/') {
+        url.push('/');
+    }
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' | b':' => {
+                url.push(b as char)
+            },
+            _ => url.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    url
+}
+
+/// FNV-1a of `site`, with the two halves xored together.
+#[doc(hidden)]
+pub const fn __site_id(site: &str) -> u16 {
+    let bytes = site.as_bytes();
+    let mut hash: u32 = 0x811c_9dc5;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+        i += 1;
+    }
+    ((hash >> 16) ^ hash) as u16
+}
+
+static ICONS: AtomicBool = AtomicBool::new(cfg!(feature = "icons"));
+
+/// Chooses between icons (`⚠ ℹ ✗`) and ASCII (`[WARN] [INFO] [ERR]`) for `trace!(@warn ...)` and
+/// friends. Icons are the default only with the `icons` feature.
+pub fn set_icons(icons: bool) {
+    ICONS.store(icons, Ordering::Relaxed);
+}
+
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy)]
+pub enum __Severity {
+    Warn,
+    Info,
+    Err,
+}
+
+impl fmt::Display for __Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let icons = ICONS.load(Ordering::Relaxed);
+        f.write_str(match (*self, icons) {
+            (__Severity::Warn, true) => "⚠",
+            (__Severity::Info, true) => "ℹ",
+            (__Severity::Err, true) => "✗",
+            (__Severity::Warn, false) => "[WARN]",
+            (__Severity::Info, false) => "[INFO]",
+            (__Severity::Err, false) => "[ERR]",
+        })
+    }
+}
+
+#[doc(hidden)]
+pub fn __trace_map<'a, M, K, V>(name: &str, map: M)
+where
+    M: IntoIterator<Item = (&'a K, &'a V)>,
+    K: 'a + Ord + fmt::Debug,
+    V: 'a + fmt::Debug,
+{
+    let mut entries: Vec<_> = map.into_iter().collect();
+    if entries.is_empty() {
+        __print(format_args!("{}: {{}}", name));
+        return;
+    }
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    let mut out = format!("{}:", name);
+    for (key, value) in entries {
+        out.push_str(&format!("\n  {:?}: {:?}", __value(key), __value(value)));
+    }
+    __print(format_args!("{}", out));
+}
+
+/// The value of an environment variable, if it has one.
+#[doc(hidden)]
+pub struct __EnvVar(Option<OsString>);
+
+impl __EnvVar {
+    pub fn get<K: AsRef<OsStr>>(key: K) -> Self {
+        __EnvVar(env::var_os(key))
+    }
+}
+
+impl fmt::Display for __EnvVar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(ref value) => write!(f, "{:?}", value),
+            None => f.write_str("<unset>"),
+        }
+    }
+}
+
+/// A wall-clock time, displayed as a UTC date and time.
+#[doc(hidden)]
+pub struct __Now(SystemTime);
+
+impl __Now {
+    pub fn now() -> Self {
+        __Now(SystemTime::now())
+    }
+}
+
+impl fmt::Display for __Now {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let since_epoch = match self.0.duration_since(UNIX_EPOCH) {
+            Ok(since_epoch) => since_epoch,
+            Err(_) => return f.write_str("(clock is set before 1970)"),
+        };
+        let secs = since_epoch.as_secs();
+        let (year, month, day) = civil_from_days(secs / 86400);
+        let time = secs % 86400;
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03} UTC",
+            year,
+            month,
+            day,
+            time / 3600,
+            time / 60 % 60,
+            time % 60,
+            since_epoch.subsec_millis(),
+        )
+    }
+}
+
+/// Turns days since 1970-01-01 into a (year, month, day) date.
+///
+/// This is Howard Hinnant's `civil_from_days`, trimmed down for dates after the epoch.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    // Count from 0000-03-01, so leap days fall at the end of each year.
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[doc(hidden)]
+pub struct __Timer<L: fmt::Display> {
+    label: L,
+    start: Instant,
+}
+
+impl<L: fmt::Display> __Timer<L> {
+    pub fn new(label: L) -> Self {
+        __Timer {
+            label,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl<L: fmt::Display> Drop for __Timer<L> {
+    fn drop(&mut self) {
+        __print(format_args!("{}: took {:.1?}", self.label, self.start.elapsed()));
+    }
+}
+
+/// The path of the function containing `f`, given the function item `f` itself.
+#[doc(hidden)]
+pub fn __fn_path<F>(_: F) -> &'static str {
+    let path = any::type_name::<F>();
+    path.strip_suffix("::f").unwrap_or(path)
+}
+
+#[doc(hidden)]
+pub struct __FnScope(&'static str);
+
+impl __FnScope {
+    pub fn enter(path: &'static str) -> Self {
+        __print(format_args!("→ {}", path));
+        INDENT.with(|indent| indent.set(indent.get() + 1));
+        __FnScope(path)
+    }
+}
+
+impl Drop for __FnScope {
+    fn drop(&mut self) {
+        INDENT.with(|indent| indent.set(indent.get().saturating_sub(1)));
+        __print(format_args!("← {}", self.0));
+    }
+}
+
+#[doc(hidden)]
+pub struct __Group<L: fmt::Display> {
+    label: L,
+    start: Instant,
+}
+
+impl<L: fmt::Display> __Group<L> {
+    pub fn new(label: L) -> Self {
+        __print(format_args!("── {} ──", label));
+        INDENT.with(|indent| indent.set(indent.get() + 1));
+        __Group {
+            label,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl<L: fmt::Display> Drop for __Group<L> {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        INDENT.with(|indent| indent.set(indent.get().saturating_sub(1)));
+        __print(format_args!("── {}: {:.1?} ──", self.label, elapsed));
+    }
+}
+
+/// How `trace!` renders values, if the `trace-format` feature is enabled.
+///
+/// Everything that implements `Debug` gets this for free, so enabling the feature changes
+/// nothing by itself. What it buys you is the ability to implement `TraceFormat` for your own
+/// types that *don't* implement `Debug`, and trace them anyway. Types that are `Debug` always
+/// use it; the blanket impl can't be overridden.
+///
+/// Without the feature `trace!` requires `Debug` directly. Some forms need `Debug` even with it,
+/// because they're built on ordinary generic functions: `@opt`, `@display_or_debug`,
+/// [`trace_range!`], [`trace_side!`], [`trace_map!`], [`TraceExt`] and [`TraceDrop`]. For
+/// `Debug` types that makes no difference to the output, since `TraceFormat` can't be
+/// implemented for them separately.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// use std::fmt;
+/// struct Secret(u64);
+/// impl eztrace::TraceFormat for Secret {
+///     fn trace_fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+///         write!(f, "Secret(****{})", self.0 % 100)
+///     }
+/// }
+/// # #[cfg(feature = "trace-format")] {
+/// let key = Secret(12345);
+/// trace!(key);
+/// // key: Secret(****45)
+/// # }
+/// ```
+pub trait TraceFormat {
+    /// Writes `self` the way `trace!` should show it. `f.alternate()` is set for `trace!(#...)`.
+    fn trace_fmt(&self, f: &mut fmt::Formatter) -> fmt::Result;
+}
+
+impl<T: ?Sized + fmt::Debug> TraceFormat for T {
+    fn trace_fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// `trace!` as a method, for the middle of a chain or a closure where a macro is awkward.
+///
+/// Both methods print the caller's file & line, a label, and the value, then hand the value back
+/// untouched. [`trace`](TraceExt::trace) labels it with its type; [`trace_as`](TraceExt::trace_as)
+/// takes the label.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// use eztrace::TraceExt;
+///
+/// let total: i32 = [1, 2, 3].iter().map(|n| n * 10).sum::<i32>().trace();
+/// // example.rs:4: i32: 60
+/// let words = "a b c".split(' ').collect::<Vec<_>>().trace_as("words");
+/// // example.rs:6: words: ["a", "b", "c"]
+/// assert_eq!((total, words.len()), (60, 3));
+/// ```
+pub trait TraceExt: Sized {
+    /// Prints `<file>:<line>: <type>: <value>` and returns `self`.
+    #[track_caller]
+    fn trace(self) -> Self;
+
+    /// Prints `<file>:<line>: <label>: <value>` and returns `self`.
+    #[track_caller]
+    fn trace_as(self, label: &str) -> Self;
+}
+
+impl<T: fmt::Debug> TraceExt for T {
+    #[track_caller]
+    fn trace(self) -> Self {
+        let location = panic::Location::caller();
+        __print(format_args!("{}:{}: {}: {:?}", location.file(), location.line(), any::type_name::<T>(), __value(&self)));
+        self
+    }
+
+    #[track_caller]
+    fn trace_as(self, label: &str) -> Self {
+        let location = panic::Location::caller();
+        __print(format_args!("{}:{}: {}: {:?}", location.file(), location.line(), label, __value(&self)));
+        self
+    }
+}
+
+/// Traces the value it holds when it's dropped, for finding out when, and in what order,
+/// destructors run.
+///
+/// It derefs to the value, so it can usually stand in for it. The trace shows the file & line
+/// where the wrapper was made, which [`trace_drop!`] does for you.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// use eztrace::TraceDrop;
+///
+/// let mut guards = vec![TraceDrop::new("first", 1), TraceDrop::new("second", 2)];
+/// *guards[1] += 10;
+/// guards.swap(0, 1);
+/// drop(guards);
+/// // example.rs:4: dropping second: 12
+/// // example.rs:4: dropping first: 1
+/// ```
+///
+/// [`into_inner`](TraceDrop::into_inner) takes the value back out without tracing.
+pub struct TraceDrop<T: fmt::Debug> {
+    value: Option<T>,
+    name: &'static str,
+    location: &'static panic::Location<'static>,
+}
+
+impl<T: fmt::Debug> TraceDrop<T> {
+    #[track_caller]
+    pub fn new(name: &'static str, value: T) -> Self {
+        TraceDrop {
+            value: Some(value),
+            name,
+            location: panic::Location::caller(),
+        }
+    }
+
+    /// Unwraps the value. Nothing is traced.
+    pub fn into_inner(mut self) -> T {
+        self.value.take().expect("TraceDrop is only emptied when it goes away")
+    }
+}
+
+impl<T: fmt::Debug> ops::Deref for TraceDrop<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value.as_ref().expect("TraceDrop is only emptied when it goes away")
+    }
+}
+
+impl<T: fmt::Debug> ops::DerefMut for TraceDrop<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.value.as_mut().expect("TraceDrop is only emptied when it goes away")
+    }
+}
+
+impl<T: fmt::Debug> Drop for TraceDrop<T> {
+    fn drop(&mut self) {
+        if let Some(ref value) = self.value {
+            __print(format_args!(
+                "{}:{}: dropping {}: {:?}",
+                self.location.file(), self.location.line(), self.name, __value(value),
+            ));
+        }
+    }
+}
+
+#[cfg(not(feature = "trace-format"))]
+#[doc(hidden)]
+pub fn __value<T: ?Sized + fmt::Debug>(value: &T) -> __Traced<'_, T> {
+    __Traced(value)
+}
+
+#[cfg(feature = "trace-format")]
+#[doc(hidden)]
+pub fn __value<T: ?Sized + TraceFormat>(value: &T) -> __Traced<'_, T> {
+    __Traced(value)
+}
+
+#[doc(hidden)]
+pub struct __Traced<'a, T: ?Sized + 'a>(&'a T);
+
+impl<'a, T: ?Sized + TraceFormat> fmt::Debug for __Traced<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        struct Plain<'a, T: ?Sized + 'a>(&'a T);
+        impl<'a, T: ?Sized + TraceFormat> fmt::Debug for Plain<'a, T> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.0.trace_fmt(f)
+            }
+        }
+        // A tee that wants the other layout formats again with `#` flipped.
+        match STYLE.with(Cell::get) {
+            Some(TeeStyle::Pretty) if !f.alternate() => write!(f, "{:#?}", Plain(self.0)),
+            Some(TeeStyle::Compact) if f.alternate() => write!(f, "{:?}", Plain(self.0)),
+            _ => self.0.trace_fmt(f),
+        }
+    }
+}
+
+/// Numbers that `@bytes`, `@ms`, and `@us` know how to convert.
+#[doc(hidden)]
+pub trait __Quantity {
+    fn __as_f64(&self) -> f64;
+}
+
+macro_rules! impl_quantity {
+    ($($ty:ty),*) => {$(
+        impl __Quantity for $ty {
+            fn __as_f64(&self) -> f64 {
+                *self as f64
+            }
+        }
+    )*};
+}
+
+impl_quantity!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+#[doc(hidden)]
+pub struct __Bytes(pub f64);
+
+impl fmt::Display for __Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const UNITS: [&str; 7] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB", "ZiB"];
+        let mut size = self.0;
+        if size.abs() < 1024.0 || !size.is_finite() {
+            return write!(f, "{} B", size);
+        }
+        let mut unit = 0;
+        size /= 1024.0;
+        while size.abs() >= 1024.0 && unit + 1 < UNITS.len() {
+            size /= 1024.0;
+            unit += 1;
+        }
+        write!(f, "{:.1} {}", size, UNITS[unit])
+    }
+}
+
+#[doc(hidden)]
+pub struct __Nanos(pub f64);
+
+impl fmt::Display for __Nanos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let nanos = self.0.abs().round();
+        if !nanos.is_finite() || nanos >= u64::MAX as f64 {
+            return write!(f, "{}ns", self.0);
+        }
+        let sign = if self.0 < 0.0 { "-" } else { "" };
+        write!(f, "{}{:?}", sign, Duration::from_nanos(nanos as u64))
+    }
+}
+
+/// When the program started, give or take; `__Throttle` counts from here.
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+
+/// When a `trace!(@throttle(...) ...)` call site last printed.
+#[doc(hidden)]
+pub struct __Throttle(AtomicU64);
+
+impl __Throttle {
+    pub const fn new() -> Self {
+        __Throttle(AtomicU64::new(0))
+    }
+
+    /// Whether at least `ms` milliseconds have passed since this last returned `true`.
+    pub fn ready(&self, ms: u64) -> bool {
+        let epoch = *EPOCH.get_or_init(Instant::now);
+        // Zero means "never printed", so count from one.
+        let now = epoch.elapsed().as_nanos() as u64 + 1;
+        let last = self.0.load(Ordering::Relaxed);
+        // Another thread may have stored a later time since `now` was taken.
+        if last != 0 && now.saturating_sub(last) < ms.saturating_mul(1_000_000) {
+            return false;
+        }
+        self.0
+            .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+    }
+}
+
+impl Default for __Throttle {
+    fn default() -> Self {
+        __Throttle::new()
+    }
+}
+
+#[doc(hidden)]
+pub fn __print_if(print: bool, args: fmt::Arguments) {
+    if print {
+        __print(args);
+    }
+}
+
+static DISTINCT_CAP: AtomicUsize = AtomicUsize::new(1000);
+
+/// Sets how many values each `trace!(@distinct ...)` call site will remember. Defaults to 1000.
+pub fn set_distinct_cap(cap: usize) {
+    DISTINCT_CAP.store(cap, Ordering::Relaxed);
+}
+
+/// The values a `trace!(@distinct ...)` call site has printed.
+#[doc(hidden)]
+pub struct __Distinct(Mutex<Option<HashSet<String>>>);
+
+impl __Distinct {
+    pub const fn new() -> Self {
+        __Distinct(Mutex::new(None))
+    }
+
+    pub fn is_new(&self, values: &str) -> bool {
+        let mut seen = lock(&self.0);
+        let seen = seen.get_or_insert_with(HashSet::new);
+        if seen.contains(values) {
+            return false;
+        }
+        if seen.len() < DISTINCT_CAP.load(Ordering::Relaxed) {
+            seen.insert(values.to_string());
+        }
+        true
+    }
+}
+
+impl Default for __Distinct {
+    fn default() -> Self {
+        __Distinct::new()
+    }
+}
+
+/// The value a `trace!(@delta ...)` call site saw last on this thread.
+#[doc(hidden)]
+pub struct __Delta(RefCell<Option<Box<dyn any::Any>>>);
+
+impl __Delta {
+    pub const fn new() -> Self {
+        __Delta(RefCell::new(None))
+    }
+
+    pub fn update<T>(&self, value: T) -> __Change<T::Output>
+    where
+        T: Copy + PartialOrd + ops::Sub + 'static,
+    {
+        let mut last = self.0.borrow_mut();
+        if let Some(last) = last.as_mut().and_then(|last| last.downcast_mut::<T>()) {
+            // Always the larger minus the smaller, so unsigned values can go down.
+            let change = match value.partial_cmp(last) {
+                Some(cmp::Ordering::Less) => ("-", *last - value),
+                Some(_) => ("+", value - *last),
+                None => ("", value - *last),
+            };
+            *last = value;
+            return __Change(Some(change));
+        }
+        *last = Some(Box::new(value));
+        __Change(None)
+    }
+}
+
+impl Default for __Delta {
+    fn default() -> Self {
+        __Delta::new()
+    }
+}
+
+#[doc(hidden)]
+pub struct __Change<T>(Option<(&'static str, T)>);
+
+impl<T: fmt::Debug> fmt::Display for __Change<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some((sign, ref change)) => write!(f, " ({}{:?})", sign, change),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The sign a `trace!(@crossing ...)` call site saw last on this thread.
+#[doc(hidden)]
+pub struct __Crossing(Cell<Option<cmp::Ordering>>);
+
+impl __Crossing {
+    pub const fn new() -> Self {
+        __Crossing(Cell::new(None))
+    }
+
+    pub fn update<T: ?Sized + PartialOrd<U>, U: Default>(&self, value: &T) -> Option<__Crossed> {
+        let sign = value.partial_cmp(&U::default())?;
+        match self.0.replace(Some(sign)) {
+            Some(last) if last != sign => Some(__Crossed(last, sign)),
+            _ => None,
+        }
+    }
+}
+
+impl Default for __Crossing {
+    fn default() -> Self {
+        __Crossing::new()
+    }
+}
+
+#[doc(hidden)]
+pub struct __Crossed(cmp::Ordering, cmp::Ordering);
+
+impl fmt::Display for __Crossed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn sign(ordering: cmp::Ordering) -> char {
+            match ordering {
+                cmp::Ordering::Less => '-',
+                cmp::Ordering::Equal => '0',
+                cmp::Ordering::Greater => '+',
+            }
+        }
+        write!(f, "({} → {})", sign(self.0), sign(self.1))
+    }
+}
+
+/// The last output a `trace!(@stuck(K) ...)` call site saw on this thread, and how many hits in a
+/// row it's been the same.
+#[doc(hidden)]
+pub struct __Stuck(RefCell<(String, usize)>);
+
+impl __Stuck {
+    pub const fn new() -> Self {
+        __Stuck(RefCell::new((String::new(), 0)))
+    }
+
+    /// Returns the streak length on the hit that makes it `hits` long.
+    pub fn update(&self, debug: String, hits: usize) -> Option<usize> {
+        let mut streak = self.0.borrow_mut();
+        if streak.1 != 0 && streak.0 == debug {
+            streak.1 = streak.1.saturating_add(1);
+        } else {
+            *streak = (debug, 1);
+        }
+        let hits = cmp::max(hits, 1);
+        if streak.1 == hits {
+            Some(hits)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for __Stuck {
+    fn default() -> Self {
+        __Stuck::new()
+    }
+}
+
+static NONE_MARKER: Mutex<Option<String>> = Mutex::new(None);
+
+/// Changes how `trace!(@opt ...)` shows `None`, which is `—` by default.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// eztrace::set_none_marker("<none>");
+/// let missing: Option<u8> = None;
+/// trace!(@opt missing);
+/// // missing: <none>
+/// # eztrace::set_none_marker("—");
+/// ```
+pub fn set_none_marker(marker: &str) {
+    *lock(&NONE_MARKER) = Some(marker.to_owned());
+}
+
+#[doc(hidden)]
+pub fn __opt<T>(option: &Option<T>) -> __Opt<'_, T> {
+    __Opt(option)
+}
+
+#[doc(hidden)]
+pub struct __Opt<'a, T: 'a>(&'a Option<T>);
+
+impl<'a, T: fmt::Debug> fmt::Display for __Opt<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self.0 {
+            Some(ref value) => write!(f, "{:?}", __value(value)),
+            None => match Realtime::get() {
+                Some(realtime) => f.write_str(realtime.none_marker.as_deref().unwrap_or("—")),
+                None => match *lock(&NONE_MARKER) {
+                    Some(ref marker) => f.write_str(marker),
+                    None => f.write_str("—"),
+                },
+            },
+        }
+    }
+}
+
+#[doc(hidden)]
+pub struct __Meta<T>(pub T);
+
+impl<T: fmt::Debug> fmt::Display for __Meta<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        struct Counter {
+            chars: usize,
+            lines: usize,
+        }
+        impl fmt::Write for Counter {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.chars += s.chars().count();
+                self.lines += s.matches('\n').count();
+                Ok(())
+            }
+        }
+        let mut counter = Counter { chars: 0, lines: 1 };
+        fmt::Write::write_fmt(&mut counter, format_args!("{:#?}", self.0))?;
+        let s = |n| if n == 1 { "" } else { "s" };
+        write!(f, "<{} char{}, {} line{}>", counter.chars, s(counter.chars), counter.lines, s(counter.lines))
+    }
+}
+
+/// Floats, and collections of them, that `@nan` knows how to check.
+#[doc(hidden)]
+pub trait __Float {
+    fn __is_finite(&self) -> bool;
+    fn __fmt(&self, f: &mut fmt::Formatter) -> fmt::Result;
+}
+
+macro_rules! impl_float {
+    ($($ty:ty),*) => {$(
+        impl __Float for $ty {
+            fn __is_finite(&self) -> bool {
+                self.is_finite()
+            }
+
+            fn __fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                if self.is_nan() {
+                    f.write_str("NaN")
+                } else if self.is_infinite() {
+                    f.write_str(if *self > 0.0 { "+Inf" } else { "-Inf" })
+                } else {
+                    fmt::Debug::fmt(self, f)
+                }
+            }
+        }
+    )*};
+}
+
+impl_float!(f32, f64);
+
+impl<T: __Float> __Float for [T] {
+    fn __is_finite(&self) -> bool {
+        self.iter().all(T::__is_finite)
+    }
+
+    fn __fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        struct Item<'a, T: 'a>(&'a T);
+        impl<'a, T: __Float> fmt::Debug for Item<'a, T> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.0.__fmt(f)
+            }
+        }
+        f.debug_list().entries(self.iter().map(Item)).finish()
+    }
+}
+
+impl<T: __Float, const N: usize> __Float for [T; N] {
+    fn __is_finite(&self) -> bool {
+        self[..].__is_finite()
+    }
+
+    fn __fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self[..].__fmt(f)
+    }
+}
+
+impl<T: __Float> __Float for Vec<T> {
+    fn __is_finite(&self) -> bool {
+        self[..].__is_finite()
+    }
+
+    fn __fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self[..].__fmt(f)
+    }
+}
+
+impl<T: ?Sized + __Float> __Float for &T {
+    fn __is_finite(&self) -> bool {
+        (**self).__is_finite()
+    }
+
+    fn __fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).__fmt(f)
+    }
+}
+
+#[doc(hidden)]
+pub struct __Nan<'a, T: ?Sized + 'a>(pub &'a T);
+
+impl<'a, T: ?Sized + __Float> fmt::Display for __Nan<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.__fmt(f)?;
+        if !self.0.__is_finite() {
+            f.write_str(" ⚠")?;
+        }
+        Ok(())
+    }
+}
+
+/// Lets `trace!(@display_or_debug ...)` find the right trait for the value it holds.
+#[doc(hidden)]
+pub struct __DisplayOrDebug<'a, T: ?Sized + 'a>(pub &'a T);
+
+#[doc(hidden)]
+pub trait __ViaDisplay<'a, T: ?Sized> {
+    fn __display_or_debug(&self) -> &'a T;
+}
+
+/// Found first, because it's for the reference that the macro calls it on.
+impl<'a, T: ?Sized + fmt::Display> __ViaDisplay<'a, T> for &__DisplayOrDebug<'a, T> {
+    fn __display_or_debug(&self) -> &'a T {
+        self.0
+    }
+}
+
+#[doc(hidden)]
+pub trait __ViaDebug<'a, T: ?Sized> {
+    fn __display_or_debug(&self) -> __AsDisplay<'a, T>;
+}
+
+impl<'a, T: ?Sized + fmt::Debug> __ViaDebug<'a, T> for __DisplayOrDebug<'a, T> {
+    fn __display_or_debug(&self) -> __AsDisplay<'a, T> {
+        __AsDisplay(self.0)
+    }
+}
+
+/// Shows a value's `Debug` where `Display` is expected.
+#[doc(hidden)]
+pub struct __AsDisplay<'a, T: ?Sized + 'a>(&'a T);
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Display for __AsDisplay<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", __value(self.0))
+    }
+}
+
+/// A field of a `trace!(@csv ...)` row, quoted if it needs to be.
+#[doc(hidden)]
+pub struct __CsvField<T: AsRef<str>>(pub T);
+
+impl<T: AsRef<str>> fmt::Display for __CsvField<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let field = self.0.as_ref();
+        if !field.contains([',', '"', '\n', '\r']) {
+            return f.write_str(field);
+        }
+        f.write_str("\"")?;
+        for (i, piece) in field.split('"').enumerate() {
+            if i != 0 {
+                f.write_str("\"\"")?;
+            }
+            f.write_str(piece)?;
+        }
+        f.write_str("\"")
+    }
+}
+
+#[doc(hidden)]
+pub struct __Variant<T>(pub T);
+
+impl<T: fmt::Debug> fmt::Display for __Variant<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(variant_name(&format!("{:?}", self.0)))
+    }
+}
+
+static ENUM_SUMMARY_LEN: AtomicUsize = AtomicUsize::new(40);
+
+/// Sets how many characters of payload `trace!(@enum ...)` shows after the variant name.
+///
+/// ```
+/// # #[macro_use] extern crate eztrace;
+/// eztrace::set_enum_summary_len(6);
+/// let parsed: Result<Vec<u8>, ()> = Ok(vec![1, 2, 3, 4]);
+/// trace!(@enum parsed);
+/// // parsed: Ok([1, 2…
+/// # eztrace::set_enum_summary_len(40);
+/// ```
+pub fn set_enum_summary_len(len: usize) {
+    ENUM_SUMMARY_LEN.store(len, Ordering::Relaxed);
+}
+
+#[doc(hidden)]
+pub struct __Summary<T>(pub T);
+
+impl<T: fmt::Debug> fmt::Display for __Summary<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let debug = format!("{:?}", self.0);
+        let name = variant_name(&debug);
+        let payload = &debug[name.len()..];
+        let len = ENUM_SUMMARY_LEN.load(Ordering::Relaxed);
+        match payload.char_indices().nth(len) {
+            Some((cut, _)) => write!(f, "{}{}…", name, &payload[..cut]),
+            None => f.write_str(&debug),
+        }
+    }
+}
+
+/// Guesses the variant name from the `Debug` output of an enum.
+fn variant_name(debug: &str) -> &str {
+    let end = debug
+        .find(|c: char| c == '(' || c == '{' || c.is_whitespace())
+        .unwrap_or(debug.len());
+    &debug[..end]
+}
+
+#[doc(hidden)]
+pub struct __Formats<'a, T: ?Sized + 'a>(
+    pub &'a T,
+    pub &'a [fn(&T, &mut fmt::Formatter) -> fmt::Result],
+);
+
+impl<'a, T: ?Sized> fmt::Display for __Formats<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (first, rest) = match self.1.split_first() {
+            Some(codes) => codes,
+            None => return Ok(()),
+        };
+        first(self.0, f)?;
+        if rest.is_empty() {
+            return Ok(());
+        }
+        f.write_str(" (")?;
+        for (i, code) in rest.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            code(self.0, f)?;
+        }
+        f.write_str(")")
+    }
+}
+
+#[doc(hidden)]
+pub fn __print_pretty(args: fmt::Arguments, width: Option<usize>, max_depth: Option<usize>) {
+    let width = width.unwrap_or_else(|| PRETTY_INDENT.load(Ordering::Relaxed));
+    let max_depth = max_depth.unwrap_or_else(|| MAX_DEPTH.load(Ordering::Relaxed));
+    if width == 4 && max_depth == usize::MAX {
+        __print(args);
+        return;
+    }
+    let mut pretty = args.to_string();
+    if max_depth != usize::MAX {
+        pretty = elide_deep(&pretty, max_depth);
+    }
+    if width != 4 {
+        pretty = reindent(&pretty, width);
+    }
+    __print(format_args!("{}", pretty));
+}
+
+/// Replaces each run of lines nested more than `max_depth` levels deep in `{:#?}` output with a
+/// single `…`, going by their 4-space indentation.
+fn elide_deep(pretty: &str, max_depth: usize) -> String {
+    let mut out = String::with_capacity(pretty.len());
+    let mut eliding = false;
+    for line in pretty.split('\n') {
+        let body = line.trim_start_matches(' ');
+        let depth = (line.len() - body.len()) / 4;
+        if depth <= max_depth {
+            eliding = false;
+        } else if eliding {
+            continue;
+        } else {
+            eliding = true;
+        }
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        if eliding {
+            for _ in 0..=max_depth {
+                out.push_str("    ");
+            }
+            out.push('…');
+        } else {
+            out.push_str(line);
+        }
+    }
+    out
+}
+
+/// Rewrites the 4-space indentation `{:#?}` produces to `width` spaces per level.
+///
+/// `Debug` escapes newlines inside strings, so every line's leading whitespace is structural.
+fn reindent(pretty: &str, width: usize) -> String {
+    let mut out = String::with_capacity(pretty.len());
+    for (i, line) in pretty.split('\n').enumerate() {
+        if i != 0 {
+            out.push('\n');
+        }
+        let body = line.trim_start_matches(' ');
+        let depth = (line.len() - body.len()) / 4;
+        for _ in 0..depth * width {
+            out.push(' ');
+        }
+        out.push_str(body);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::fmt;
+    use std::io;
+    use std::sync::{Arc, Mutex, MutexGuard};
+
+    thread_local! {
+        static CAPTURED: RefCell<Option<String>> = const { RefCell::new(None) };
+    }
+
+    /// Diverts a line into the current thread's capture buffer, if `capture` is running.
+    pub fn captured(args: fmt::Arguments) -> bool {
+        CAPTURED.with(|captured| match *captured.borrow_mut() {
+            Some(ref mut out) => {
+                fmt::Write::write_fmt(out, args).unwrap();
+                true
+            },
+            None => false,
+        })
+    }
+
+    static SERIAL: Mutex<()> = Mutex::new(());
+
+    /// Keeps tests that touch global settings from running concurrently.
+    fn serial() -> MutexGuard<'static, ()> {
+        SERIAL.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Runs `f`, returning everything it traced on this thread instead of printing it.
+    fn capture<F: FnOnce()>(f: F) -> String {
+        let _serial = serial();
+        CAPTURED.with(|captured| *captured.borrow_mut() = Some(String::new()));
+        f();
+        CAPTURED.with(|captured| captured.borrow_mut().take().unwrap())
+    }
+
+    /// A sink that tests can read back from.
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl SharedBuf {
+        /// The lines written so far that mention `marker`; other tests may be printing too.
+        fn lines_with(&self, marker: &str) -> Vec<String> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .split_inclusive('\n')
+                .filter(|line| line.contains(marker))
+                .map(String::from)
+                .collect()
+        }
+    }
+
+    impl io::Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    #[allow(clippy::useless_format)]
+    fn no_move() {
+        let string = format!("hey");
+        trace!(string, 9);
+        trace!(string, string);
+        trace!(
+            string,
+            string,
+            string,
+        );
+    }
+
+    #[test]
+    fn single_eval_per_arg() {
+        let mut n = 0;
+        fn incr(i: &mut usize) { *i += 1; }
+        trace!(
+            "check that each argument",
+            "is only evaluated",
+            "once",
+            incr(&mut n),
+        );
+        trace!(n);
+        assert_eq!(n, 1);
+
+        fn key(i: &mut usize) -> &'static str {
+            *i += 1;
+            "EZTRACE_SINGLE_EVAL"
+        }
+        trace!(@env key(&mut n));
+        assert_eq!(n, 2);
+    }
+
+    #[test]
+    fn empty() {
+        trace!();
+    }
+
+    #[test]
+    fn single() {
+        let hello = "hello";
+        trace!(hello);
+    }
+
+    #[test]
+    fn literal() {
+        trace!("hi");
+        trace!(0xED);
+    }
+
+    #[test]
+    fn here() {
+        let (out, line) = {
+            let mut line = 0;
+            let out = capture(|| {
+                line = line!(); trace!(@here "reached cleanup");
+                trace!(@here "{braces} are not format codes");
+            });
+            (out, line)
+        };
+        assert_eq!(
+            out,
+            format!(
+                "{file}:{}: reached cleanup\n{file}:{}: {{braces}} are not format codes\n",
+                line,
+                line + 1,
+                file = file!(),
+            ),
+        );
+    }
+
+    #[test]
+    fn ret() {
+        fn compute() -> String {
+            trace!(@ret format!("{}", 17))
+        }
+        let mut value = None;
+        let out = capture(|| value = Some(compute()));
+        assert_eq!(value.unwrap(), "17");
+        assert!(out.starts_with("eztrace.rs:"));
+        assert!(out.ends_with(": returns \"17\"\n"));
+    }
+
+    #[test]
+    fn fmts() {
+        let n = 255u8;
+        let m = 16;
+        let out = capture(|| {
+            trace!(@fmts("{:?}", "{:#x}", "{:#b}") n);
+            trace!(@x n, m);
+            trace!(@fmts("{:>4}") m);
+        });
+        assert_eq!(out, "n: 255 (0xff, 0b11111111)\nn, m: 255 (0xff) 16 (0x10)\nm:   16\n");
+    }
+
+    #[test]
+    fn sink() {
+        use {LineEnding, reset_sink, set_line_ending, set_sink};
+        let _serial = serial();
+        let (buf, tee) = (SharedBuf::default(), SharedBuf::default());
+        set_sink(buf.clone());
+        ::add_tee(tee.clone(), ::TeeStyle::Compact);
+        set_line_ending(LineEnding::CrLf);
+        let sink_marker = [1, 2];
+        trace!(#sink_marker);
+        set_line_ending(LineEnding::Lf);
+        trace!(sink_marker);
+        reset_sink();
+        ::clear_tees();
+        set_line_ending(if cfg!(windows) { LineEnding::CrLf } else { LineEnding::Lf });
+        trace!("sink_marker after reset");
+        assert_eq!(
+            buf.lines_with("sink_marker"),
+            ["sink_marker: [\r\n", "sink_marker: [1, 2]\n"],
+        );
+        assert_eq!(buf.lines_with("    1,"), ["    1,\r\n"]);
+        // Tees follow the setting too.
+        assert_eq!(tee.lines_with("sink_marker"), ["sink_marker: [1, 2]\r\n", "sink_marker: [1, 2]\n"]);
+    }
+
+    #[test]
+    fn barrier() {
+        use std::fs::{self, File};
+        use std::io::BufWriter;
+        use std::time::Duration;
+        use {add_tee, barrier, clear_tees, reset_sink, set_file_sink, set_flush_policy, FlushPolicy, TeeStyle};
+        let _serial = serial();
+        let path = ::std::env::temp_dir().join(format!("eztrace-barrier-{}.log", ::std::process::id()));
+        set_file_sink(File::create(&path).unwrap()).unwrap();
+        let tee = SharedBuf::default();
+        add_tee(BufWriter::new(tee.clone()), TeeStyle::Compact);
+        set_flush_policy(FlushPolicy::Batched {
+            lines: 1000,
+            interval: Duration::from_secs(3600),
+        });
+        trace!("barrier marker");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        barrier().unwrap();
+        assert!(fs::read_to_string(&path).unwrap().lines().any(|line| line == "barrier marker"));
+        assert_eq!(tee.lines_with("barrier marker"), ["barrier marker\n"]);
+        set_flush_policy(FlushPolicy::EveryLine);
+        clear_tees();
+        reset_sink();
+        // Nothing to sync any more, but flushing stdout still works.
+        barrier().unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn flush_policy() {
+        use std::time::Duration;
+        use {flush, reset_sink, set_flush_policy, set_sink, FlushPolicy};
+        let _serial = serial();
+        let buf = SharedBuf::default();
+        set_sink(buf.clone());
+        set_flush_policy(FlushPolicy::Batched {
+            lines: 1000,
+            interval: Duration::from_secs(3600),
+        });
+        trace!("batch marker 1");
+        trace!("batch marker 2");
+        assert!(buf.lines_with("batch marker").is_empty());
+        flush();
+        assert_eq!(buf.lines_with("batch marker"), ["batch marker 1\n", "batch marker 2\n"]);
+
+        set_flush_policy(FlushPolicy::Batched {
+            lines: 1000,
+            interval: Duration::from_secs(0),
+        });
+        trace!("batch marker 3");
+        assert_eq!(buf.lines_with("batch marker 3"), ["batch marker 3\n"]);
+
+        set_flush_policy(FlushPolicy::Batched {
+            lines: 1000,
+            interval: Duration::from_secs(3600),
+        });
+        trace!("batch marker 4");
+        // Changing the policy, or the sink, doesn't lose anything.
+        set_flush_policy(FlushPolicy::EveryLine);
+        assert_eq!(buf.lines_with("batch marker 4"), ["batch marker 4\n"]);
+        set_flush_policy(FlushPolicy::Batched {
+            lines: 1,
+            interval: Duration::from_secs(3600),
+        });
+        trace!("batch marker 5");
+        assert_eq!(buf.lines_with("batch marker 5"), ["batch marker 5\n"]);
+        set_flush_policy(FlushPolicy::Batched {
+            lines: 1000,
+            interval: Duration::from_secs(3600),
+        });
+        trace!("batch marker 6");
+        reset_sink();
+        set_flush_policy(FlushPolicy::EveryLine);
+        assert_eq!(buf.lines_with("batch marker 6"), ["batch marker 6\n"]);
+    }
+
+    #[test]
+    fn cmp() {
+        let actual = vec![1, 2, 3];
+        let expected = vec![1, 2, 4];
+        let mut results = (true, false);
+        let out = capture(|| {
+            results = (trace_cmp!(actual, expected), trace_cmp!(actual.len(), 3));
+        });
+        assert_eq!(results, (false, true));
+        assert_eq!(
+            out,
+            "actual == expected? NO\n  actual: [1, 2, 3]\n  expected: [1, 2, 4]\n\
+             actual.len() == 3? YES: 3\n",
+        );
+        // Still usable afterwards; nothing was moved.
+        assert_eq!(actual.len(), expected.len());
+    }
+
+    #[test]
+    fn tick() {
+        let out = capture(|| {
+            for _ in 0..10 {
+                trace!(@tick 3);
+            }
+            trace!("done");
+            for _ in 0..2 {
+                trace!(@tick 0);
+            }
+            trace!(@tick);
+            // A bare trace!() with nothing to print still ends the line.
+            ::set_show_location(false);
+            trace!();
+            trace!(@tick);
+            trace!();
+            ::set_show_location(true);
+            trace!("after");
+        });
+        assert_eq!(out, "...\ndone\n...\n.\nafter\n");
+        // Leave the next test a fresh line.
+        capture(|| {
+            trace!("-");
+        });
+    }
 
-#[cfg(test)]
-mod tests {
+    #[cfg(feature = "trace-format")]
     #[test]
-    fn no_move() {
-        let string = format!("hey");
-        trace!(string, 9);
-        trace!(string, string);
-        trace!(
-            string,
-            string,
-            string,
+    fn trace_format() {
+        #[derive(PartialEq)]
+        struct Opaque;
+        impl ::TraceFormat for Opaque {
+            fn trace_fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(if f.alternate() { "<opaque, pretty>" } else { "<opaque>" })
+            }
+        }
+        let opaque = Opaque;
+        let out = capture(|| {
+            trace!(opaque, 1);
+            trace!(#opaque);
+            trace_cmp!(opaque, Opaque);
+        });
+        assert_eq!(out, "opaque, 1: <opaque> 1\nopaque: <opaque, pretty>\nopaque == Opaque? YES: <opaque>\n");
+    }
+
+    #[test]
+    fn show_location() {
+        let out = capture(|| {
+            ::set_show_location(false);
+            trace!();
+            trace!(#);
+            trace!("label");
+            trace!(@here "here");
+            ::set_show_location(true);
+        });
+        assert!(out.starts_with("label\neztrace.rs:"), "{:?}", out);
+        assert!(out.ends_with(": here\n"), "{:?}", out);
+        let out = capture(|| {
+            trace!();
+        });
+        assert!(out.starts_with("eztrace.rs:"));
+    }
+
+    #[test]
+    fn sigil_matrix() {
+        let x = [1];
+        let (out, line) = {
+            let mut line = 0;
+            let out = capture(|| {
+                line = line!(); trace!(); trace!(#);
+                trace!("a \"label\"");
+                trace!(#"a \"label\"");
+                trace!(x);
+                trace!(#x);
+            });
+            (out, line)
+        };
+        assert_eq!(
+            out,
+            format!(
+                "eztrace.rs:{0}\neztrace.rs:{0}:43\n\
+                 a \"label\"\n\"a \\\"label\\\"\"\n\
+                 x: [1]\nx: [\n    1,\n]\n",
+                line,
+            ),
         );
     }
 
     #[test]
-    fn single_eval_per_arg() {
-        let mut n = 0;
-        fn incr(i: &mut usize) { *i += 1; }
-        trace!(
-            "check that each argument",
-            "is only evaluated",
-            "once",
-            incr(&mut n),
+    fn here_marker() {
+        let out = capture(|| {
+            ::set_here_marker("• here");
+            trace!();
+            trace!(#);
+            trace!(@here "unchanged");
+            ::set_here_marker("at {location}!");
+            trace!();
+            ::reset_here_marker();
+            trace!();
+        });
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[..2], ["• here", "• here"]);
+        assert!(lines[2].starts_with("eztrace.rs:") && lines[2].ends_with(": unchanged"));
+        assert!(lines[3].starts_with("at eztrace.rs:") && lines[3].ends_with('!'));
+        assert!(lines[4].starts_with("eztrace.rs:"), "{:?}", out);
+    }
+
+    #[test]
+    fn delta() {
+        let out = capture(|| {
+            for &(n, f) in &[(3u8, 0.5), (5, 0.25), (5, 1.0)] {
+                trace!(@delta n);
+                trace!(@delta f * 2.0);
+            }
+        });
+        assert_eq!(
+            out,
+            "n: 3\nf * 2.0: 1.0\nn: 5 (+2)\nf * 2.0: 0.5 (-0.5)\nn: 5 (+0)\nf * 2.0: 2.0 (+1.5)\n",
         );
-        trace!(n);
-        assert_eq!(n, 1);
+        // Each thread keeps its own.
+        let out = ::std::thread::spawn(|| capture(|| {
+            for i in 0..2 {
+                trace!(@delta i);
+            }
+        })).join().unwrap();
+        assert_eq!(out, "i: 0\ni: 1 (+1)\n");
+        // Unsigned values can go down, and NaN has no sign to give.
+        let out = capture(|| {
+            for &n in &[5u32, 3, 0, 7] {
+                trace!(@delta n);
+            }
+            for &f in &[1.0, f64::NAN] {
+                trace!(@delta f);
+            }
+        });
+        assert_eq!(out, "n: 5\nn: 3 (-2)\nn: 0 (-3)\nn: 7 (+7)\nf: 1.0\nf: NaN (NaN)\n");
     }
 
     #[test]
-    fn empty() {
-        trace!();
+    fn crossing() {
+        let out = capture(|| {
+            for &x in &[2, 1, 0, 0, -1, 0, 5] {
+                trace!(@crossing x);
+            }
+            for &f in &[-1.0, f64::NAN, -2.0, 1.0] {
+                trace!(@crossing f);
+            }
+            trace!(@crossing 1u8);
+        });
+        assert_eq!(
+            out,
+            "x: 0 (+ → 0)\nx: -1 (0 → -)\nx: 0 (- → 0)\nx: 5 (0 → +)\nf: 1.0 (- → +)\n",
+        );
     }
 
     #[test]
-    fn single() {
-        let hello = "hello";
-        trace!(hello);
+    fn trace_ext() {
+        use TraceExt;
+
+        let mut lines = Vec::new();
+        let out = capture(|| {
+            let n = 3u8.trace();
+            lines.push(line!());
+            assert_eq!(n, 3);
+            let words = vec!["a", "b"].trace_as("words").into_iter().map(str::len).collect::<Vec<_>>();
+            lines.push(line!());
+            assert_eq!(words, [1, 1]);
+            let owned = String::from("moved").trace();
+            lines.push(line!());
+            assert_eq!(owned, "moved");
+            assert_eq!(Some(4).map(|x| x.trace_as("in a closure") * 2), Some(8));
+            lines.push(line!());
+        });
+        assert_eq!(
+            out,
+            format!(
+                "eztrace.rs:{}: u8: 3\n\
+                 eztrace.rs:{}: words: [\"a\", \"b\"]\n\
+                 eztrace.rs:{}: alloc::string::String: \"moved\"\n\
+                 eztrace.rs:{}: in a closure: 4\n",
+                lines[0] - 1, lines[1] - 1, lines[2] - 1, lines[3] - 1,
+            ),
+        );
     }
 
     #[test]
-    fn literal() {
-        trace!("hi");
-        trace!(0xED);
+    fn trace_drop() {
+        use TraceDrop;
+
+        #[derive(Debug)]
+        struct Token(&'static str);
+        let first = line!() + 2;
+        let out = capture(|| {
+            let a = trace_drop!(Token("a"));
+            {
+                let mut b = TraceDrop::new("b", vec![1]);
+                b.push(2);
+                assert_eq!(b.len(), 2);
+                trace!("end of scope");
+            }
+            let kept = trace_drop!(5u8).into_inner();
+            assert_eq!(kept, 5);
+            assert_eq!(a.0, "a");
+        });
+        assert_eq!(
+            out,
+            format!(
+                "end of scope\n\
+                 eztrace.rs:{}: dropping b: [1, 2]\n\
+                 eztrace.rs:{}: dropping Token(\"a\"): Token(\"a\")\n",
+                first + 2,
+                first,
+            ),
+        );
+    }
+
+    #[test]
+    fn stuck() {
+        let out = capture(|| {
+            for &x in &[1, 1, 2, 2, 2, 2, 2, 3, 2, 2, 2] {
+                trace!(@stuck(3) x);
+            }
+            for s in &["a", "b", "b"] {
+                trace!(@stuck(0) s);
+            }
+            for _ in 0..4 {
+                trace!(@stuck(1 + 1) "same");
+            }
+        });
+        assert_eq!(
+            out,
+            "x: 2 (same for 3 hits)\n\
+             x: 2 (same for 3 hits)\n\
+             s: \"a\" (same for 1 hits)\n\
+             s: \"b\" (same for 1 hits)\n\
+             \"same\": \"same\" (same for 2 hits)\n",
+        );
+    }
+
+    #[test]
+    fn opt() {
+        let some = Some("x");
+        let none: Option<Vec<u8>> = None;
+        let by_ref = &Some(Some(3));
+        let out = capture(|| {
+            trace!(@opt some, none, by_ref, *by_ref, None::<()>,);
+            ::set_none_marker("(nothing)");
+            trace!(@opt none);
+            ::set_none_marker("—");
+        });
+        assert_eq!(
+            out,
+            "some, none, by_ref, *by_ref, None::<()>: \"x\" — Some(3) Some(3) —\nnone: (nothing)\n",
+        );
+    }
+
+    #[test]
+    fn meta() {
+        let grid = vec![vec![0u8; 3]; 2];
+        let out = capture(|| {
+            trace!(@meta grid, 7, "ünï");
+        });
+        assert_eq!(format!("{:#?}", grid).len(), 95);
+        assert_eq!(out, "grid, 7, \"ünï\": <95 chars, 12 lines> <1 char, 1 line> <5 chars, 1 line>\n");
+    }
+
+    #[test]
+    fn nan() {
+        let fine = [1.5f32, -0.0];
+        let bad = vec![f64::NAN, 2.0, f64::NEG_INFINITY];
+        let r = &f64::INFINITY;
+        let out = capture(|| {
+            trace!(@nan 0.1 + 0.2, f32::NAN, r, *r);
+            trace!(@nan fine, fine[..], bad, Vec::<f64>::new());
+        });
+        assert_eq!(
+            out,
+            "0.1 + 0.2, f32::NAN, r, *r: 0.30000000000000004 NaN ⚠ +Inf ⚠ +Inf ⚠\n\
+             fine, fine[..], bad, Vec::<f64>::new(): [1.5, -0.0] [1.5, -0.0] [NaN, 2.0, -Inf] ⚠ []\n",
+        );
+    }
+
+    #[test]
+    fn variant() {
+        #[derive(Debug)]
+        #[allow(dead_code)]
+        enum State {
+            Idle,
+            Running { pid: u32 },
+            Done(i32),
+        }
+        let states = [State::Idle, State::Running { pid: 7 }, State::Done(0)];
+        let out = capture(|| {
+            trace!(@variant states[0], states[1], states[2]);
+            trace!(@variant Some(&states[2]));
+        });
+        assert_eq!(out, "states[0], states[1], states[2]: Idle Running Done\nSome(&states[2]): Some\n");
+        assert_eq!(super::variant_name("Node { next: None }"), "Node");
+        assert_eq!(super::variant_name(""), "");
+    }
+
+    #[test]
+    fn depth() {
+        fn walk(n: u32) -> u32 {
+            let _depth = ::DepthGuard::new();
+            if n == 0 {
+                trace!(@depth "bottom");
+                return 0;
+            }
+            trace!(@depth "down" = n);
+            walk(n - 1)
+        }
+        let out = capture(|| {
+            trace!(@depth);
+            walk(2);
+            ::push_depth();
+            ::push_depth();
+            trace!(@depth #(1, ));
+            trace!("not drawn");
+            assert_eq!(trace!(@depth @ret 5), 5);
+            ::pop_depth();
+            ::set_depth_marker("> ");
+            trace!(@depth "custom");
+            ::set_depth_marker("│ ");
+            ::pop_depth();
+            ::pop_depth();
+            let unwound = ::std::panic::catch_unwind(|| {
+                let _depth = ::DepthGuard::default();
+                ::push_depth();
+                panic!("unwinding");
+            });
+            assert!(unwound.is_err());
+            assert_eq!(::depth(), 0);
+            trace!(@depth "top");
+        });
+        let line = line!();
+        assert_eq!(
+            out,
+            format!(
+                "eztrace.rs:{}\n\
+                 │ down: 2\n\
+                 │ │ down: 1\n\
+                 │ │ │ bottom\n\
+                 │ │ (1,): (\n\
+                 │ │     1,\n\
+                 │ │ )\n\
+                 not drawn\n\
+                 │ │ eztrace.rs:{}: returns 5\n\
+                 > custom\n\
+                 top\n",
+                line - 22,
+                line - 16,
+            ),
+        );
+    }
+
+    #[test]
+    fn display_or_debug() {
+        use std::fmt;
+
+        struct DisplayOnly;
+        impl fmt::Display for DisplayOnly {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("shown")
+            }
+        }
+        #[derive(Debug)]
+        #[allow(dead_code)]
+        struct DebugOnly(u8);
+        #[derive(Debug)]
+        struct Both;
+        impl fmt::Display for Both {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("displayed")
+            }
+        }
+        fn generic<T: fmt::Debug>(value: T) {
+            trace!(@display_or_debug value);
+        }
+
+        let out = capture(|| {
+            trace!(@display_or_debug DisplayOnly, DebugOnly(1), Both);
+            trace!(@display_or_debug String::from("owned"), vec![Both], 3.5);
+            generic("debug");
+        });
+        assert_eq!(
+            out,
+            "DisplayOnly, DebugOnly(1), Both: shown DebugOnly(1) displayed\n\
+             String::from(\"owned\"), vec![Both], 3.5: owned [Both] 3.5\n\
+             value: \"debug\"\n",
+        );
+    }
+
+    #[test]
+    fn enum_summary() {
+        #[derive(Debug)]
+        #[allow(dead_code)]
+        enum Msg {
+            Quit,
+            Load(Vec<u32>),
+            Move { x: i32, y: i32 },
+        }
+        let msgs = [Msg::Quit, Msg::Load((0..100).collect()), Msg::Move { x: 1, y: 2 }];
+        let out = capture(|| {
+            trace!(@enum msgs[0], msgs[1], msgs[2]);
+            ::set_enum_summary_len(3);
+            trace!(@enum msgs[1], msgs[2]);
+            trace!(@enum Some("ünïcödé"), Some("1234"));
+            ::set_enum_summary_len(0);
+            trace!(@enum msgs[1], Some(()));
+            ::set_enum_summary_len(40);
+        });
+        assert_eq!(
+            out,
+            "msgs[0], msgs[1], msgs[2]: Quit Load([0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, … Move { x: 1, y: 2 }\n\
+             msgs[1], msgs[2]: Load([0… Move { …\n\
+             Some(\"ünïcödé\"), Some(\"1234\"): Some(\"ü… Some(\"1…\n\
+             msgs[1], Some(()): Load… Some…\n",
+        );
+    }
+
+    #[test]
+    fn group() {
+        fn search(haystack: &[i32]) -> Option<usize> {
+            trace_group!("search"; {
+                for (i, &x) in haystack.iter().enumerate() {
+                    if x == 3 {
+                        trace!(@tick);
+                        trace!(@tick);
+                        return Some(i);
+                    }
+                }
+                None
+            })
+        }
+        let mut found = None;
+        let out = capture(|| {
+            found = trace_group!("outer"; {
+                trace!(#[1]);
+                search(&[1, 2, 3])
+            });
+        });
+        assert_eq!(found, Some(2));
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines[..6], [
+            "── outer ──",
+            "  [1]: [",
+            "      1,",
+            "  ]",
+            "  ── search ──",
+            "    ..",
+        ]);
+        assert!(lines[6].starts_with("  ── search: "), "{:?}", out);
+        assert!(lines[7].starts_with("── outer: "), "{:?}", out);
+        assert_eq!(lines.len(), 8);
+    }
+
+    #[test]
+    fn quantities() {
+        let (small, size, huge) = (512u16, 1572864usize, -3.0 * 1024f64.powi(4));
+        let (elapsed, latency) = (1234, 250i16);
+        let out = capture(|| {
+            trace!(@bytes small, size, huge);
+            trace!(@ms elapsed, -1);
+            trace!(@us latency, 1e12, f64::NAN);
+        });
+        assert_eq!(
+            out,
+            "small, size, huge: 512 B 1.5 MiB -3.0 TiB\n\
+             elapsed, -1: 1.234s -1ms\n\
+             latency, 1e12, f64::NAN: 250µs 1000000s NaNns\n",
+        );
+    }
+
+    #[test]
+    fn specs() {
+        let (ratio, flags, name) = (0.12345, 0x2a, "bob");
+        let out = capture(|| {
+            trace!(ratio => ":>8.2", flags => ":#06x", name);
+            trace!(name => ":<5", name => ":^7", name => ":>5",);
+            trace!(ratio => ":.0", flags => ":b", flags => ":e", ratio => ":?");
+        });
+        assert_eq!(
+            out,
+            "ratio, flags, name:     0.12 0x002a \"bob\"\n\
+             name, name, name: bob     bob     bob\n\
+             ratio, flags, flags, ratio: 0 101010 4.2e1 0.12345\n",
+        );
+    }
+
+    #[test]
+    fn cfg() {
+        let n = 1;
+        let out = capture(|| {
+            trace!(@cfg(test) n);
+            trace!(@cfg(not(test)) "compiled out", undefined_variable);
+            trace!(@cfg(all(test, target_os = "none")) n);
+            trace!(@cfg(any(test, target_os = "none")) "label");
+        });
+        assert_eq!(out, "n: 1\nlabel\n");
+    }
+
+    #[test]
+    fn ring() {
+        let mut ring = super::Ring {
+            lines: Default::default(),
+            capacity: 3,
+            open: false,
+        };
+        for text in &["a\n", "b\n  c\n", ".", ".", "\nd\n", "e\n"] {
+            ring.push(text);
+        }
+        assert_eq!(ring.lines, ["..", "d", "e"]);
+
+        let out = capture(|| {
+            ::enable_ring(10);
+            trace!("ring marker");
+            assert!(::dump_ring().iter().any(|line| line == "ring marker"));
+            ::disable_ring();
+        });
+        assert_eq!(out, "ring marker\n");
+        assert_eq!(::dump_ring(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn realtime() {
+        use super::REALTIME_LINE_BYTES;
+        use std::sync::atomic::Ordering;
+
+        let realtime = super::Realtime::new(2);
+        for i in 0..3 {
+            realtime.push(format_args!("line {}", i));
+        }
+        assert_eq!(realtime.dropped.load(Ordering::Relaxed), 1);
+        assert_eq!(realtime.pop().as_deref(), Some("line 0"));
+        realtime.push(format_args!("line 3"));
+        assert_eq!(realtime.pop().as_deref(), Some("line 1"));
+        assert_eq!(realtime.pop().as_deref(), Some("line 3"));
+        assert_eq!(realtime.pop(), None);
+        super::Realtime::new(0).push(format_args!("nowhere"));
+
+        // Too long, and cut where `…` would split a character.
+        let long = format!("{}é{}", "a".repeat(REALTIME_LINE_BYTES - 4), "b".repeat(10));
+        realtime.push(format_args!("{}", long));
+        let line = realtime.pop().unwrap();
+        assert_eq!(line, format!("{}…", "a".repeat(REALTIME_LINE_BYTES - 4)));
+        assert!(line.len() <= REALTIME_LINE_BYTES);
+
+        let _serial = serial();
+        ::enable_realtime_buffer(1000);
+        let threads: Vec<_> = (0..4).map(|t| ::std::thread::spawn(move || {
+            for i in 0..50 {
+                trace!("realtime marker" = t, i);
+            }
+        })).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let lines: Vec<_> = ::drain_realtime_buffer().into_iter().filter(|l| l.contains("realtime marker")).collect();
+        assert_eq!(::realtime_dropped(), 0);
+        ::disable_realtime_buffer();
+        assert_eq!(lines.len(), 200);
+        assert!(lines.contains(&"realtime marker, i: 3 49".to_string()));
+        assert_eq!(::drain_realtime_buffer(), Vec::<String>::new());
+
+        // Markers are read without locking: these traces finish while the locks are held.
+        ::set_here_marker("rt {location} {location}");
+        ::set_none_marker("nothing");
+        ::enable_realtime_buffer(10);
+        ::reset_here_marker();
+        let held = (super::lock(&super::HERE_MARKER), super::lock(&super::NONE_MARKER));
+        let (sender, receiver) = ::std::sync::mpsc::channel();
+        ::std::thread::spawn(move || {
+            let line = line!(); trace!();
+            trace!(@opt None::<u8>);
+            sender.send(line).unwrap();
+        });
+        let line = receiver.recv_timeout(::std::time::Duration::from_secs(10)).expect("realtime trace blocked");
+        drop(held);
+        let lines = ::drain_realtime_buffer();
+        ::disable_realtime_buffer();
+        ::set_none_marker("—");
+        let location = format!("{}:{}", file!(), line);
+        assert_eq!(lines, [format!("rt {} {}", location, location), "None::<u8>: nothing".to_string()]);
+    }
+
+    #[test]
+    fn channel() {
+        use std::sync::mpsc;
+        let _serial = serial();
+        let (sender, receiver) = mpsc::sync_channel(100);
+        ::set_channel(sender);
+        let channel_marker = [1];
+        trace!(channel_marker);
+        trace!(#channel_marker);
+        trace!(@tick);
+        trace!(@tick);
+        trace!("channel_marker after ticks");
+        ::clear_channel();
+        trace!("channel_marker after clearing");
+        // Anything else printing meanwhile will have been sent too.
+        let lines: Vec<String> = receiver.try_iter().collect();
+        let lines: Vec<&str> = lines
+            .iter()
+            .map(|line| &line[..])
+            .filter(|line| line.contains("channel_marker") || ["    1,", "]", ".."].contains(line))
+            .collect();
+        assert_eq!(lines, [
+            "channel_marker: [1]",
+            "channel_marker: [",
+            "    1,",
+            "]",
+            "..",
+            "channel_marker after ticks",
+        ]);
+
+        // Full: the rest are dropped.
+        let (sender, receiver) = mpsc::sync_channel(1);
+        ::set_channel(sender);
+        trace!("channel_marker 1");
+        trace!("channel_marker 2");
+        assert_eq!(receiver.try_iter().count(), 1);
+        // Disconnected: nothing happens.
+        drop(receiver);
+        trace!("channel_marker 3");
+        ::clear_channel();
+    }
+
+    #[test]
+    fn tee() {
+        use {add_tee, clear_tees, TeeStyle};
+        let (compact, pretty) = (SharedBuf::default(), SharedBuf::default());
+        add_tee(compact.clone(), TeeStyle::Compact);
+        add_tee(pretty.clone(), TeeStyle::Pretty);
+        let tee_marker = (1, "two");
+        let out = capture(|| {
+            trace!(tee_marker);
+            trace!(#tee_marker);
+            trace!("tee_marker label");
+            trace!(tee_marker.0 => ":03");
+        });
+        clear_tees();
+        trace!("tee_marker after clearing");
+        assert_eq!(
+            out,
+            "tee_marker: (1, \"two\")\n\
+             tee_marker: (\n    1,\n    \"two\",\n)\n\
+             tee_marker label\n\
+             tee_marker.0: 001\n",
+        );
+        assert_eq!(compact.lines_with("tee_marker"), [
+            "tee_marker: (1, \"two\")\n",
+            "tee_marker: (1, \"two\")\n",
+            "tee_marker label\n",
+            "tee_marker.0: 001\n",
+        ]);
+        let pretty = String::from_utf8(pretty.0.lock().unwrap().clone()).unwrap();
+        let pretty_tuple = "tee_marker: (\n    1,\n    \"two\",\n)\n";
+        assert_eq!(pretty.matches(pretty_tuple).count(), 2, "{:?}", pretty);
+        assert!(pretty.contains("tee_marker label\ntee_marker.0: 001\n"), "{:?}", pretty);
+        assert!(!pretty.contains("after clearing"));
+    }
+
+    #[test]
+    fn distinct() {
+        let out = capture(|| {
+            for (i, input) in [3, 1, 3, 3, 2, 1].iter().enumerate() {
+                trace!(@distinct input);
+                trace!(@distinct input, i % 2);
+            }
+        });
+        assert_eq!(
+            out,
+            "input: 3\ninput, i % 2: 3 0\n\
+             input: 1\ninput, i % 2: 1 1\n\
+             input, i % 2: 3 1\n\
+             input: 2\ninput, i % 2: 2 0\n",
+        );
+
+        let out = capture(|| {
+            ::set_distinct_cap(1);
+            for input in &[1, 2, 1, 2] {
+                trace!(@distinct input);
+            }
+            ::set_distinct_cap(1000);
+        });
+        assert_eq!(out, "input: 1\ninput: 2\ninput: 2\n");
+    }
+
+    #[test]
+    fn addr_of() {
+        let buf = vec![0u8; 16];
+        let view = &buf;
+        let out = capture(|| {
+            trace!(@addr_of buf, *view);
+        });
+        let addr = format!("{:p}", &buf);
+        assert_eq!(out, format!("&buf, &*view: {} {}\n", addr, addr));
+        assert_eq!(buf.len(), 16);
+    }
+
+    #[test]
+    fn level() {
+        use std::panic;
+        use {level, with_level};
+        fn bump(n: &mut i32) -> &'static str {
+            *n += 1;
+            "hidden"
+        }
+        let mut evaluated = 0;
+        let out = capture(|| {
+            trace!(@level(1) bump(&mut evaluated));
+            with_level(2, || {
+                trace!(@level(2) "two");
+                with_level(0, || trace!(@level(1) "hidden"));
+                trace!(@level(1) "one");
+                assert_eq!(level(), 2);
+            });
+            trace!(@level(0) "zero");
+        });
+        assert_eq!(out, "two\none\nzero\n");
+        assert_eq!(evaluated, 0);
+        assert_eq!(level(), 0);
+
+        let result = panic::catch_unwind(|| with_level(5, || panic!("expected panic")));
+        assert!(result.is_err());
+        assert_eq!(level(), 0);
+    }
+
+    #[test]
+    fn labels() {
+        #[derive(Debug)]
+        struct Kind {
+            r#type: u8,
+        }
+        let r#type = "raw";
+        let kind = Kind { r#type: 1 };
+        let pair = (1, (2, 3));
+        let v = [10, 20];
+        let out = capture(|| {
+            trace!(r#type, kind.r#type);
+            trace!(pair.0, pair.1.0, v.len(), v[1], v.iter().map(|x| x * 2).sum::<i32>());
+            trace!(Kind { r#type: 2 }.r#type, vec![{ 1 }]);
+            trace!(#Kind { r#type: 3 }.r#type);
+            trace!(@variant Kind { r#type: 4 });
+            trace!(pair.0 + pair.1.1, -pair.0);
+            trace!(
+                v[0] // first
+                    + v[1]
+            );
+        });
+        assert_eq!(
+            out,
+            "r#type, kind.r#type: \"raw\" 1\n\
+             pair.0, pair.1.0, v.len(), v[1], v.iter().map(|x| x * 2).sum::<i32>(): 1 2 2 20 60\n\
+             Kind { r#type: 2 }.r#type, vec![{ 1 }]: 2 [1]\n\
+             Kind { r#type: 3 }.r#type: 3\n\
+             Kind { r#type: 4 }: Kind\n\
+             pair.0 + pair.1.1, -pair.0: 4 -1\n\
+             v[0] + v[1]: 30\n",
+        );
+    }
+
+    #[test]
+    fn time() {
+        use std::thread::sleep;
+        use std::time::Duration;
+        fn parse_sum(input: &str) -> Result<i32, ::std::num::ParseIntError> {
+            trace_time!("parse", {
+                let mut sum = 0;
+                for word in input.split(' ') {
+                    sum += word.parse::<i32>()?;
+                }
+                Ok(sum)
+            })
+        }
+        let mut results = (0, Ok(0), Ok(0));
+        let out = capture(|| {
+            results = (
+                trace_time!("phase", {
+                    let x = 20;
+                    sleep(Duration::from_millis(2));
+                    x * 2 + 2
+                }),
+                parse_sum("1 2 3"),
+                parse_sum("1 two 3"),
+            );
+        });
+        assert_eq!(results.0, 42);
+        assert_eq!(results.1, Ok(6));
+        assert!(results.2.is_err());
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines.len(), 3, "{:?}", out);
+        assert!(lines[0].starts_with("phase: took ") && lines[0].ends_with("ms"), "{:?}", out);
+        assert!(lines[1].starts_with("parse: took "), "{:?}", out);
+        assert!(lines[2].starts_with("parse: took "), "{:?}", out);
+    }
+
+    #[test]
+    fn throttle() {
+        use std::thread::sleep;
+        use std::time::Duration;
+        let mut evaluated = 0;
+        let out = capture(|| {
+            for i in 0..6 {
+                if i == 3 {
+                    sleep(Duration::from_millis(120));
+                }
+                trace!(@throttle(100) i, { evaluated += 1; evaluated });
+            }
+        });
+        assert_eq!(out, "i, { evaluated += 1; evaluated }: 0 1\ni, { evaluated += 1; evaluated }: 3 4\n");
+        assert_eq!(evaluated, 6);
+    }
+
+    #[test]
+    fn throttle_threads() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::thread;
+        static THROTTLE: super::__Throttle = super::__Throttle::new();
+        static READY: AtomicUsize = AtomicUsize::new(0);
+        let threads: Vec<_> = (0..8).map(|_| thread::spawn(|| {
+            for _ in 0..100_000 {
+                if THROTTLE.ready(0) {
+                    READY.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        })).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert!(READY.load(Ordering::Relaxed) > 0);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn tokio_task_ids() {
+        use std::collections::HashSet;
+        use std::future::Future;
+        use std::pin::Pin;
+        use std::task::{Context, Poll};
+        use tokio::runtime::Builder;
+
+        // No `async` in this edition.
+        struct Task(u32);
+        impl Future for Task {
+            type Output = ();
+            fn poll(self: Pin<&mut Self>, _: &mut Context) -> Poll<()> {
+                trace!("task marker" = self.0);
+                Poll::Ready(())
+            }
+        }
+        let out = capture(|| {
+            let runtime = Builder::new_current_thread().build().unwrap();
+            let tasks: Vec<_> = (0..3).map(|i| runtime.spawn(Task(i))).collect();
+            for task in tasks {
+                runtime.block_on(task).unwrap();
+            }
+            trace!("outside a task");
+        });
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 4);
+        let ids: HashSet<&str> = lines[..3].iter().map(|line| {
+            assert!(line.starts_with("[task-"), "{:?}", line);
+            assert!(line.contains("task marker"), "{:?}", line);
+            &line[..line.find(']').unwrap()]
+        }).collect();
+        assert_eq!(ids.len(), 3);
+        assert_eq!(lines[3], "outside a task");
+    }
+
+    #[test]
+    fn prefix_fn() {
+        use {clear_prefix_fn, set_prefix_fn};
+        let out = capture(|| {
+            set_prefix_fn(Box::new(|| String::from("[ctx] ")));
+            trace_group!("group"; {
+                trace!(#[1]);
+            });
+            set_prefix_fn(Box::new(|| panic!("expected panic")));
+            trace!("unprefixed");
+            clear_prefix_fn();
+            trace!("cleared");
+        });
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines[..4], [
+            "[ctx] ── group ──",
+            "[ctx]   [1]: [",
+            "[ctx]       1,",
+            "[ctx]   ]",
+        ]);
+        assert!(lines[4].starts_with("[ctx] ── group: "), "{:?}", out);
+        assert_eq!(lines[5..], ["unprefixed", "cleared"]);
+    }
+
+    #[test]
+    fn sanitize() {
+        let evil = "\x1b[31mred\r\0";
+        let out = capture(|| {
+            ::set_sanitize(true);
+            trace!(evil => "");
+            trace!("tab\there\nnext line: ünïcødé");
+            ::set_sanitize(false);
+            trace!(evil => "");
+        });
+        assert_eq!(
+            out,
+            "evil: \\u{1b}[31mred\\r\\u{0}\n\
+             tab\\there\nnext line: ünïcødé\n\
+             evil: \x1b[31mred\r\0\n",
+        );
+    }
+
+    #[test]
+    fn line_filter() {
+        use {clear_line_filter, clear_tag, set_line_filter, set_tag};
+        let x = 1;
+        let out = capture(|| {
+            set_tag("TAG");
+            set_line_filter(Box::new(|line| !line.contains("noise") && !line.starts_with("TAG")));
+            trace!("noise");
+            trace!(x);
+            trace!(#["noise"]);
+            trace!(#[x]);
+            set_line_filter(Box::new(|_| panic!("expected panic")));
+            trace!("kept");
+            clear_line_filter();
+            clear_tag();
+            trace!("noise, unfiltered");
+        });
+        assert_eq!(out, "TAG|x: 1\nTAG|[x]: [\nTAG|    1,\nTAG|]\nTAG|kept\nnoise, unfiltered\n");
+
+        // Filtered lines are still restyled for tees.
+        let pretty = SharedBuf::default();
+        let out = capture(|| {
+            ::add_tee(pretty.clone(), ::TeeStyle::Pretty);
+            set_line_filter(Box::new(|line| !line.contains("noise")));
+            trace!(x, "noise");
+            trace!([x]);
+            clear_line_filter();
+            ::clear_tees();
+        });
+        assert_eq!(out, "[x]: [1]\n");
+        let pretty = String::from_utf8(pretty.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(pretty, "[x]: [\n    1,\n]\n");
+    }
+
+    #[test]
+    fn tag() {
+        use {clear_prefix_fn, clear_tag, set_prefix_fn, set_tag};
+        let out = capture(|| {
+            set_tag("EZTRACE");
+            set_prefix_fn(Box::new(|| String::from("[ctx] ")));
+            trace_group!("group"; {
+                trace!(#[1]);
+                trace!(@tick);
+                trace!(@tick);
+            });
+            clear_prefix_fn();
+            trace!("no prefix fn");
+            clear_tag();
+            trace!("untagged");
+        });
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines[..5], [
+            "EZTRACE|[ctx] ── group ──",
+            "EZTRACE|[ctx]   [1]: [",
+            "EZTRACE|[ctx]       1,",
+            "EZTRACE|[ctx]   ]",
+            "EZTRACE|[ctx]   ..",
+        ]);
+        assert!(lines[5].starts_with("EZTRACE|[ctx] ── group: "), "{:?}", out);
+        assert_eq!(lines[6..], ["EZTRACE|no prefix fn", "untagged"]);
+    }
+
+    #[test]
+    fn unsized_values() {
+        use std::fmt::Debug;
+        let s: &str = "hi";
+        let slice: &[i32] = &[1, 2];
+        let object: &dyn Debug = &Some(3);
+        let boxed: Box<dyn Debug> = Box::new('x');
+        let out = capture(|| {
+            trace!(s, *s, slice, *slice, object, *object, boxed, *boxed);
+            trace!(#*slice);
+            trace!(@fmts("{:?}", "{}") *s);
+            trace!(*s => ":>4", *object => ":?");
+            trace!(@distinct *object);
+        });
+        assert_eq!(
+            out,
+            "s, *s, slice, *slice, object, *object, boxed, *boxed: \
+             \"hi\" \"hi\" [1, 2] [1, 2] Some(3) Some(3) 'x' 'x'\n\
+             *slice: [\n    1,\n    2,\n]\n\
+             *s: \"hi\" (hi)\n\
+             *s, *object:   hi Some(3)\n\
+             *object: Some(3)\n",
+        );
+    }
+
+    #[test]
+    fn now() {
+        use std::time::{Duration, UNIX_EPOCH};
+        use __Now;
+        let at = |secs, millis| {
+            __Now(UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_millis(millis)).to_string()
+        };
+        assert_eq!(at(0, 0), "1970-01-01 00:00:00.000 UTC");
+        assert_eq!(at(1704207845, 123), "2024-01-02 15:04:05.123 UTC");
+        assert_eq!(at(951868799, 999), "2000-02-29 23:59:59.999 UTC");
+        assert_eq!(at(951868800, 0), "2000-03-01 00:00:00.000 UTC");
+        assert_eq!(__Now(UNIX_EPOCH - Duration::from_secs(1)).to_string(), "(clock is set before 1970)");
+
+        let out = capture(|| {
+            trace!(@now);
+            trace!(@now "label");
+        });
+        let lines: Vec<_> = out.lines().collect();
+        assert!(lines[0].starts_with("eztrace.rs:") && lines[0].ends_with(" UTC"), "{:?}", out);
+        assert!(lines[1].contains(": label: "), "{:?}", out);
+    }
+
+    #[test]
+    fn link() {
+        use std::path::{Path, PathBuf};
+        use __Link;
+        assert_eq!(super::file_url(Path::new("/src/my crate/lib.rs")), "file:///src/my%20crate/lib.rs");
+        assert_eq!(super::file_url(Path::new("C:\\src\\lib.rs")), "file:///C:/src/lib.rs");
+        let linked = __Link {
+            file: "src/main.rs",
+            line: 7,
+            base: Some(PathBuf::from("/work")),
+        };
+        // Linked only when rendered for stdout.
+        assert_eq!(linked.to_string(), "src/main.rs:7");
+        super::TO_STDOUT.with(|to_stdout| to_stdout.set(true));
+        let rendered = linked.to_string();
+        super::TO_STDOUT.with(|to_stdout| to_stdout.set(false));
+        assert_eq!(rendered, "\x1b]8;;file:///work/src/main.rs\x1b\\src/main.rs:7\x1b]8;;\x1b\\");
+        let plain = __Link {
+            base: None,
+            ..linked
+        };
+        super::TO_STDOUT.with(|to_stdout| to_stdout.set(true));
+        let rendered = plain.to_string();
+        super::TO_STDOUT.with(|to_stdout| to_stdout.set(false));
+        assert_eq!(rendered, "src/main.rs:7");
+
+        // A sink is never assumed to be a terminal.
+        let _serial = serial();
+        let buf = SharedBuf::default();
+        ::set_sink(buf.clone());
+        let x = 5;
+        let line = line!(); trace!(@link x, "link marker");
+        ::reset_sink();
+        assert_eq!(
+            buf.lines_with("link marker"),
+            [format!("{}:{}: x, \"link marker\": 5 \"link marker\"\n", file!(), line)],
+        );
+
+        // Nor are tees and the ring, even while the same trace is linked on stdout.
+        ::add_tee(buf.clone(), ::TeeStyle::Compact);
+        ::enable_ring(4);
+        ::__print(format_args!("{}: tee marker", linked));
+        let ring = ::dump_ring();
+        ::disable_ring();
+        ::clear_tees();
+        assert_eq!(buf.lines_with("tee marker"), ["src/main.rs:7: tee marker\n"]);
+        assert_eq!(ring, ["src/main.rs:7: tee marker"]);
+    }
+
+    #[test]
+    fn site_id() {
+        use __site_id;
+        // FNV-1a("a") is 0xe40c292c.
+        assert_eq!(__site_id("a"), 0xe40c ^ 0x292c);
+        assert_eq!(__site_id(""), 0x811c ^ 0x9dc5);
+        let x = 5;
+        let (out, line) = {
+            let mut line = 0;
+            let out = capture(|| {
+                line = line!(); trace!(@id x, x + 1);
+                trace!(@id("label") x);
+                trace!(@id("label") x);
+            });
+            (out, line)
+        };
+        let here = format!("[{:04x}] x, x + 1: 5 6\n", __site_id(&format!("{}:{}", file!(), line)));
+        let labelled = format!("[{:04x}] x: 5\n", __site_id("label"));
+        assert_eq!(out, here + &labelled + &labelled);
+    }
+
+    #[test]
+    fn severity() {
+        let x = 1;
+        let out = capture(|| {
+            ::set_icons(false);
+            trace!(@warn x);
+            trace!(@info x, x + 1,);
+            trace!(@err "oops");
+            ::set_icons(true);
+            trace!(@warn "careful");
+            trace!(@info x);
+            trace!(@err x);
+            ::set_icons(cfg!(feature = "icons"));
+        });
+        assert_eq!(out, "[WARN] x: 1\n[INFO] x, x + 1: 1 2\n[ERR] oops\n⚠ careful\nℹ x: 1\n✗ x: 1\n");
+    }
+
+    #[test]
+    fn env() {
+        use std::env;
+        let out = capture(|| {
+            env::set_var("EZTRACE_TEST_SET", "some value");
+            env::set_var("EZTRACE_TEST_EMPTY", "");
+            env::remove_var("EZTRACE_TEST_UNSET");
+            trace!(@env "EZTRACE_TEST_SET");
+            trace!(@env "EZTRACE_TEST_EMPTY", String::from("EZTRACE_TEST_UNSET"),);
+        });
+        let lines: Vec<_> = out.lines().map(|line| line.splitn(3, ':').nth(2).unwrap()).collect();
+        assert_eq!(lines, [
+            " EZTRACE_TEST_SET = \"some value\"",
+            " EZTRACE_TEST_EMPTY = \"\"",
+            " EZTRACE_TEST_UNSET = <unset>",
+        ]);
+    }
+
+    #[test]
+    fn const_values() {
+        const X: u32 = 6 * 7;
+        const NAME: &str = "eztrace";
+        let out = capture(|| {
+            trace!(@const X, X * 2, NAME.len(), [X; 2],);
+            trace!(@const NAME);
+        });
+        assert_eq!(out, "X, X * 2, NAME.len(), [X; 2]: 42 84 7 [42, 42]\nNAME: \"eztrace\"\n");
+    }
+
+    #[test]
+    fn stream() {
+        use std::panic;
+        let v: &[i32] = &[1, 2, 3];
+        let mut evaluated = 0;
+        let out = capture(|| {
+            let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                trace!(@stream { evaluated += 1; v.len() }, v[0], v[10], { evaluated += 1; v[1] });
+            }));
+            assert!(result.is_err());
+            trace!(@stream v[2]);
+        });
+        assert_eq!(evaluated, 1);
+        assert_eq!(out, "{ evaluated += 1; v.len() }: 3\nv[0]: 1\nv[2]: 3\n");
+    }
+
+    #[test]
+    fn fn_enter() {
+        use std::panic;
+        fn fib(n: u32) -> u32 {
+            trace_fn_enter!(n);
+            if n < 2 {
+                n
+            } else {
+                fib(n - 1) + fib(n - 2)
+            }
+        }
+        fn explode() {
+            trace_fn_enter!();
+            panic!("expected panic");
+        }
+        let out = capture(|| {
+            assert_eq!(fib(2), 1);
+            assert!(panic::catch_unwind(explode).is_err());
+            trace!("back");
+        });
+        assert_eq!(
+            out,
+            "→ eztrace::tests::fn_enter::fib\n  n: 2\n\
+             \x20 → eztrace::tests::fn_enter::fib\n    n: 1\n  ← eztrace::tests::fn_enter::fib\n\
+             \x20 → eztrace::tests::fn_enter::fib\n    n: 0\n  ← eztrace::tests::fn_enter::fib\n\
+             ← eztrace::tests::fn_enter::fib\n\
+             → eztrace::tests::fn_enter::explode\n← eztrace::tests::fn_enter::explode\n\
+             back\n",
+        );
+    }
+
+    #[test]
+    fn range() {
+        let (x, y) = (42, 150.5);
+        let mut results = vec![];
+        let out = capture(|| {
+            results.push(trace_range!(x, 0..100));
+            results.push(trace_range!(y, 0.0..=100.0));
+            results.push(trace_range!(x, 42..));
+            results.push(trace_range!(x + 1, ..43));
+        });
+        assert_eq!(results, [true, false, true, false]);
+        assert_eq!(
+            out,
+            "x: 42 (in 0..100)\n\
+             y: 150.5 (OUT OF RANGE 0.0..=100.0)\n\
+             x: 42 (in 42..)\n\
+             x + 1: 43 (OUT OF RANGE ..43)\n",
+        );
+    }
+
+    #[test]
+    fn side() {
+        let long = vec!["a", "bb", "c"];
+        let short = ["a", "b"];
+        let out = capture(|| {
+            trace_side!(long, short);
+            trace_side!(short, long[..1]);
+        });
+        assert_eq!(
+            out,
+            "  long | short\n  \"a\"  | \"a\"\n≠ \"bb\" | \"b\"\n≠ \"c\"  | —\n  \
+             short | long[..1]\n  \"a\"   | \"a\"\n≠ \"b\"   | —\n",
+        );
+        let out = capture(|| {
+            let empty: [u8; 0] = [];
+            trace_side!(empty, empty);
+        });
+        assert_eq!(out, "  empty | empty\n");
+    }
+
+    #[test]
+    fn map() {
+        use std::collections::{BTreeMap, HashMap};
+        let hashed: HashMap<_, _> = (0..20).map(|i| (19 - i, i * i)).collect();
+        let ordered: BTreeMap<_, _> = vec![("b", vec![1]), ("a", vec![])].into_iter().collect();
+        let empty: HashMap<u8, u8> = HashMap::new();
+        let out = capture(|| {
+            trace_map!(hashed);
+            trace_map!(ordered);
+            trace_map!(empty);
+        });
+        let mut expected = String::from("hashed:\n");
+        for k in 0..20 {
+            expected += &format!("  {}: {}\n", k, (19 - k) * (19 - k));
+        }
+        expected += "ordered:\n  \"a\": []\n  \"b\": [1]\nempty: {}\n";
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn bool() {
+        let v: Vec<u8> = vec![];
+        let mut taken = false;
+        let out = capture(|| {
+            if trace_bool!(v.is_empty()) || trace_bool!(v[0] == 1) {
+                taken = trace_bool!(!taken);
+            }
+            if trace_bool!(v.len() > 3) && trace_bool!(v[3] == 1) {
+                taken = false;
+            }
+        });
+        assert!(taken);
+        assert_eq!(out, "v.is_empty(): true\n!taken: true\nv.len() > 3: false\n");
+    }
+
+    #[test]
+    fn named() {
+        let items = [3, 1, 2];
+        let mut calls = 0;
+        let out = capture(|| {
+            trace!("max" = items.iter().copied().fold(0, i32::max));
+            trace!(items.len(), "sum" = { calls += 1; items.iter().sum::<i32>() }, items[0],);
+            trace!("first" = items[0] => ":03", items[1] => ":>3", "" = 1);
+            trace!(1, "two", "three" = 3);
+        });
+        assert_eq!(calls, 1);
+        assert_eq!(
+            out,
+            "max: 3\n\
+             items.len(), sum, items[0]: 3 6 3\n\
+             first, items[1], : 003   1 1\n\
+             1, \"two\", three: 1 \"two\" 3\n",
+        );
+    }
+
+    #[test]
+    fn csv() {
+        let mut total = 0;
+        let out = capture(|| {
+            for i in 0..3 {
+                total += i;
+                trace!(@csv i, total, "a,b", [i, 1]);
+            }
+            trace!(@csv total);
+            trace!(@csv (total, 1.5), "x,y".len());
+        });
+        assert_eq!(
+            out,
+            "i,total,\"\"\"a,b\"\"\",\"[i, 1]\"\n\
+             0,0,\"\"\"a,b\"\"\",\"[0, 1]\"\n\
+             1,1,\"\"\"a,b\"\"\",\"[1, 1]\"\n\
+             2,3,\"\"\"a,b\"\"\",\"[2, 1]\"\n\
+             total\n\
+             3\n\
+             \"(total, 1.5)\",\"\"\"x,y\"\".len()\"\n\
+             \"(3, 1.5)\",3\n",
+        );
     }
 
     #[test]
@@ -198,12 +5393,13 @@ mod tests {
 
 
         #[derive(Debug, Default)]
+        #[allow(dead_code)] // Only ever read through Debug.
         struct Coords {
             x: f32,
             y: f32,
         }
         trace!(#);
-        // eztrace.rs:7
+        // eztrace.rs:7:9
         trace!(#"hello");
         // "hello"
         let zero = Coords::default();
@@ -212,6 +5408,161 @@ mod tests {
         //     x: 0.0,
         //     y: 0.0,
         // }
+        trace!(@#2 zero);
+        // zero: Coords {
+        //   x: 0.0,
+        //   y: 0.0,
+        // }
+    }
+
+    #[test]
+    fn nested_trace() {
+        use std::fmt;
+        use {reset_sink, set_sink};
+
+        struct Noisy;
+        impl fmt::Debug for Noisy {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                trace!("inside debug");
+                f.write_str("Noisy")
+            }
+        }
+        let out = capture(|| {
+            trace!(Noisy);
+            trace!("after");
+        });
+        assert_eq!(out, "Noisy: Noisy\nafter\n");
+
+        let _serial = serial();
+        let buf = SharedBuf::default();
+        set_sink(buf.clone());
+        trace!("nested marker", Noisy);
+        reset_sink();
+        assert_eq!(buf.lines_with("nested marker"), ["\"nested marker\", Noisy: \"nested marker\" Noisy\n"]);
+        assert!(buf.lines_with("inside debug").is_empty());
+    }
+
+    #[test]
+    fn on_panic() {
+        use std::panic;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static DUMPS: AtomicUsize = AtomicUsize::new(0);
+        let before = DUMPS.load(Ordering::SeqCst);
+        ::on_panic(|| {
+            DUMPS.fetch_add(1, Ordering::SeqCst);
+        });
+        let result = panic::catch_unwind(|| panic!("expected panic"));
+        assert!(result.is_err());
+        assert!(DUMPS.load(Ordering::SeqCst) > before);
+    }
+
+    #[test]
+    fn on_panic_while_tracing() {
+        use std::cell::Cell;
+        use std::fmt;
+        use std::panic;
+
+        struct Panicky;
+        impl fmt::Debug for Panicky {
+            fn fmt(&self, _: &mut fmt::Formatter) -> fmt::Result {
+                panic!("expected panic in Debug");
+            }
+        }
+        thread_local! {
+            // The hook stays installed, so keep it quiet in other tests.
+            static ARMED: Cell<bool> = const { Cell::new(false) };
+        }
+        ::on_panic(|| {
+            if ARMED.with(Cell::get) {
+                trace!("panic dump");
+            }
+        });
+        let out = capture(|| {
+            ARMED.with(|armed| armed.set(true));
+            let result = panic::catch_unwind(|| { trace!(Panicky); });
+            assert!(result.is_err());
+            trace!("still tracing");
+            let result = panic::catch_unwind(|| panic!("expected panic"));
+            assert!(result.is_err());
+            ARMED.with(|armed| armed.set(false));
+        });
+        assert!(out.ends_with("still tracing\npanic dump\n"), "{:?}", out);
+        assert_eq!(out.matches("panic dump").count(), 1);
+    }
+
+    #[test]
+    fn reindent() {
+        #[derive(Debug)]
+        #[allow(dead_code)]
+        struct Outer {
+            inner: Inner,
+        }
+        #[derive(Debug)]
+        #[allow(dead_code)]
+        struct Inner {
+            name: &'static str,
+        }
+        let outer = Outer { inner: Inner { name: "  padded\n" } };
+        let pretty = format!("{:#?}", outer);
+        assert_eq!(super::reindent(&pretty, 4), pretty);
+        assert_eq!(
+            super::reindent(&pretty, 2),
+            "Outer {\n  inner: Inner {\n    name: \"  padded\\n\",\n  },\n}",
+        );
+        assert_eq!(super::reindent(&pretty, 0), "Outer {\ninner: Inner {\nname: \"  padded\\n\",\n},\n}");
+    }
+
+    #[test]
+    fn max_depth() {
+        #[derive(Debug)]
+        #[allow(dead_code)]
+        struct Tree {
+            name: &'static str,
+            children: Vec<Tree>,
+        }
+        let leaf = |name| Tree { name, children: vec![] };
+        let tree = Tree {
+            name: "root",
+            children: vec![Tree { name: "a", children: vec![leaf("a1"), leaf("a2")] }, leaf("b")],
+        };
+        let out = capture(|| {
+            trace!(@#depth(2) tree);
+            trace!(@#depth(0) tree.children[1], "  indented string\n");
+            ::set_max_depth(1);
+            ::set_pretty_indent(2);
+            trace!(#tree);
+            ::set_pretty_indent(4);
+            ::set_max_depth(usize::MAX);
+            trace!(@#depth(9) tree.children[1]);
+        });
+        assert_eq!(
+            out,
+            "tree: Tree {\n\
+            \x20   name: \"root\",\n\
+            \x20   children: [\n\
+            \x20       Tree {\n\
+            \x20           …\n\
+            \x20       },\n\
+            \x20       Tree {\n\
+            \x20           …\n\
+            \x20       },\n\
+            \x20   ],\n\
+             }\n\
+             tree.children[1], \"  indented string\\n\": Tree {\n\
+            \x20   …\n\
+             } \"  indented string\\n\"\n\
+             tree: Tree {\n\
+            \x20 name: \"root\",\n\
+            \x20 children: [\n\
+            \x20   …\n\
+            \x20 ],\n\
+             }\n\
+             tree.children[1]: Tree {\n\
+            \x20   name: \"b\",\n\
+            \x20   children: [],\n\
+             }\n",
+        );
+        assert_eq!(super::elide_deep("a\n        b\nc", 0), "a\n    …\nc");
     }
 }
 
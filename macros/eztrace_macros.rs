@@ -0,0 +1,168 @@
+//! Attribute macros for `eztrace`. Use them through eztrace's `proc-macro` feature, which
+//! re-exports them; the docs are there.
+
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+/// Traces a function's arguments each time it's called. See `eztrace::trace_args`.
+#[proc_macro_attribute]
+pub fn trace_args(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if let Some(token) = attr.into_iter().next() {
+        return error(token.span(), "`#[trace_args]` doesn't take any arguments");
+    }
+    let mut tokens: Vec<TokenTree> = item.into_iter().collect();
+    let params = match params(&tokens) {
+        Some(params) => params,
+        None => return error(Span::call_site(), "`#[trace_args]` goes on a function"),
+    };
+    let names = param_names(params);
+    let body = match tokens.last_mut() {
+        Some(&mut TokenTree::Group(ref mut body)) if body.delimiter() == Delimiter::Brace => body,
+        _ => return error(Span::call_site(), "`#[trace_args]` goes on a function with a body"),
+    };
+    if !names.is_empty() {
+        let mut stream = trace_call(names);
+        stream.extend(body.stream());
+        let mut traced = Group::new(Delimiter::Brace, stream);
+        traced.set_span(body.span());
+        *body = traced;
+    }
+    tokens.into_iter().collect()
+}
+
+/// Finds the parameter list: the first parenthesized group after `fn name<...>`.
+fn params(tokens: &[TokenTree]) -> Option<TokenStream> {
+    let start = tokens.iter().position(|t| is_ident(t, "fn"))? + 2;
+    let mut depth = 0;
+    let mut previous = None;
+    for token in &tokens[start..] {
+        match *token {
+            TokenTree::Group(ref group) if depth == 0 && group.delimiter() == Delimiter::Parenthesis => {
+                return Some(group.stream());
+            },
+            TokenTree::Punct(ref p) => depth = angle_depth(depth, p, previous),
+            _ => {},
+        }
+        previous = Some(token);
+    }
+    None
+}
+
+/// The names of the parameters that are plain bindings, in order.
+///
+/// `self` in any of its forms counts. Destructuring patterns, and names starting with `_`,
+/// are left out.
+fn param_names(params: TokenStream) -> Vec<Ident> {
+    let mut names = Vec::new();
+    let mut param: Vec<TokenTree> = Vec::new();
+    let mut depth = 0;
+    let mut previous: Option<TokenTree> = None;
+    for token in params {
+        if let TokenTree::Punct(ref p) = token {
+            depth = angle_depth(depth, p, previous.as_ref());
+            if depth == 0 && p.as_char() == ',' {
+                names.extend(binding(&param));
+                param.clear();
+                previous = None;
+                continue;
+            }
+        }
+        param.push(token.clone());
+        previous = Some(token);
+    }
+    names.extend(binding(&param));
+    names
+}
+
+/// The name a single parameter binds, if it's a simple one.
+fn binding(param: &[TokenTree]) -> Option<Ident> {
+    let mut pattern = Vec::new();
+    let mut tokens = param.iter();
+    while let Some(token) = tokens.next() {
+        match *token {
+            // An attribute, like `#[allow(unused)]`.
+            TokenTree::Punct(ref p) if p.as_char() == '#' => {
+                tokens.next();
+            },
+            // The `:` before the type; `::` in a path is two joint puncts.
+            TokenTree::Punct(ref p) if p.as_char() == ':' && p.spacing() == Spacing::Alone => break,
+            TokenTree::Punct(ref p) if p.as_char() == ':' => return None,
+            // A lifetime, as in `&'a self`.
+            TokenTree::Punct(ref p) if p.as_char() == '\'' => {
+                tokens.next();
+            },
+            TokenTree::Punct(ref p) if p.as_char() == '&' => {},
+            TokenTree::Ident(ref ident) if ident.to_string() == "mut" || ident.to_string() == "ref" => {},
+            _ => pattern.push(token),
+        }
+    }
+    match pattern[..] {
+        [TokenTree::Ident(ident)] if !ident.to_string().starts_with('_') => Some(ident.clone()),
+        _ => None,
+    }
+}
+
+/// Tracks `<`/`>` nesting, so the commas in `HashMap<K, V>` don't split parameters.
+fn angle_depth(depth: usize, p: &Punct, previous: Option<&TokenTree>) -> usize {
+    let arrow = match previous {
+        Some(TokenTree::Punct(previous)) => previous.as_char() == '-',
+        _ => false,
+    };
+    match p.as_char() {
+        '<' => depth + 1,
+        '>' if !arrow => depth.saturating_sub(1),
+        _ => depth,
+    }
+}
+
+fn is_ident(token: &TokenTree, name: &str) -> bool {
+    match *token {
+        TokenTree::Ident(ref ident) => ident.to_string() == name,
+        _ => false,
+    }
+}
+
+/// `::eztrace::trace!(a, b);`
+fn trace_call(names: Vec<Ident>) -> TokenStream {
+    let mut args = Vec::new();
+    for (i, name) in names.into_iter().enumerate() {
+        if i != 0 {
+            args.push(TokenTree::Punct(Punct::new(',', Spacing::Alone)));
+        }
+        args.push(TokenTree::Ident(name));
+    }
+    let mut call = path(&["eztrace", "trace"]);
+    call.extend(vec![
+        TokenTree::Punct(Punct::new('!', Spacing::Alone)),
+        TokenTree::Group(Group::new(Delimiter::Parenthesis, args.into_iter().collect())),
+        TokenTree::Punct(Punct::new(';', Spacing::Alone)),
+    ]);
+    call
+}
+
+/// `::a::b`
+fn path(segments: &[&str]) -> TokenStream {
+    let mut path = Vec::new();
+    for segment in segments {
+        path.push(TokenTree::Punct(Punct::new(':', Spacing::Joint)));
+        path.push(TokenTree::Punct(Punct::new(':', Spacing::Alone)));
+        path.push(TokenTree::Ident(Ident::new(segment, Span::call_site())));
+    }
+    path.into_iter().collect()
+}
+
+/// `compile_error!("message")`, pointing at `span`.
+fn error(span: Span, message: &str) -> TokenStream {
+    let mut message = Literal::string(message);
+    message.set_span(span);
+    let error = vec![
+        TokenTree::Ident(Ident::new("compile_error", span)),
+        TokenTree::Punct(Punct::new('!', Spacing::Alone)),
+        TokenTree::Group(Group::new(Delimiter::Parenthesis, TokenTree::Literal(message).into())),
+    ];
+    error.into_iter().map(|mut token| {
+        token.set_span(span);
+        token
+    }).collect()
+}
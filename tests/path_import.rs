@@ -0,0 +1,104 @@
+//! The macros should work when imported by path instead of with `#[macro_use]`.
+
+extern crate eztrace;
+
+mod imported {
+    use eztrace::trace;
+
+    #[derive(Debug, Default)]
+    #[allow(dead_code)]
+    struct Coords {
+        x: f32,
+        y: f32,
+    }
+
+    #[test]
+    fn every_arm() {
+        let (a, b) = (3, 4);
+        let zero = Coords::default();
+        trace!();
+        trace!(#);
+        trace!("label");
+        trace!(#"label");
+        trace!(a, b);
+        trace!(a, b,);
+        trace!(#zero);
+        trace!(@#2 zero);
+        trace!(a => ":>4", b);
+        trace!(@here "here");
+        trace!(@now);
+        trace!(@now "now");
+        trace!(@tick);
+        trace!(@tick 2);
+        trace!(@cfg(test) a);
+        trace!(@level(0) a);
+        assert_eq!(trace!(@ret a + b), 7);
+        trace!(@throttle(100) a);
+        trace!(@distinct a);
+        trace!(@addr_of a);
+        trace!(@csv a, b);
+        trace!(@fmts("{:?}", "{:#b}") a);
+        trace!(@x a, b);
+        trace!(@variant Some(a));
+        trace!(@bytes a);
+        trace!(@ms a);
+        trace!(@us b);
+        trace!(@env "PATH", "NO_SUCH_VARIABLE");
+        trace!(@warn a, b);
+        trace!(@opt Some(a));
+        trace!(@id a);
+        trace!(@id("site") a);
+        trace!(@link a);
+        trace!(@delta a);
+        trace!(@crossing a);
+        trace!(@meta a, b);
+        trace!(@stream a, b);
+        trace!(@const u8::MAX);
+        trace!(@nan 1.0, [f32::NAN]);
+        trace!("named" = a, b);
+        trace!(@stuck(2) a);
+        trace!(@enum Some(a));
+        trace!(@depth a);
+        trace!(@#depth(1) a);
+        trace!(@display_or_debug a, [a]);
+        drop(::eztrace::trace_drop!(a));
+        ::eztrace::TraceExt::trace_as(a, "a");
+    }
+}
+
+mod renamed {
+    use eztrace::trace as t;
+
+    #[test]
+    fn every_arm() {
+        let (a, b) = (3, 4);
+        t!();
+        t!(#a);
+        t!(@#2 a, b);
+        t!(@csv a, b);
+        t!(@x a);
+        t!(@level(0) @cfg(test) a);
+        assert_eq!(t!(@ret a), 3);
+    }
+}
+
+mod qualified {
+    #[test]
+    fn every_arm() {
+        let (a, b) = (3, 4);
+        ::eztrace::trace!(a, b);
+        ::eztrace::trace!(#a);
+        ::eztrace::trace!(@csv a, b);
+        ::eztrace::trace!(@fmts("{:?}", "{:#b}") a);
+        ::eztrace::trace!(@cfg(test) @level(0) "nested");
+        assert!(::eztrace::trace_cmp!(a, 3));
+        assert_eq!(::eztrace::trace_time!("time", a + b), 7);
+        assert_eq!(::eztrace::trace_group!("group"; { ::eztrace::trace!(b); b }), 4);
+        ::eztrace::trace_panic_hook!(a);
+        ::eztrace::trace_fn_enter!(a, b);
+        assert!(::eztrace::trace_range!(a, 0..b));
+        ::eztrace::trace_side!([a], [b]);
+        assert!(::eztrace::trace_bool!(a < b));
+        ::eztrace::trace_map!(::std::collections::BTreeMap::from([(a, b)]));
+    }
+}
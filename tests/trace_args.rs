@@ -0,0 +1,83 @@
+//! `#[trace_args]` traces what the function was called with.
+//!
+//! Run with `cargo test --features proc-macro --test trace_args`.
+
+extern crate eztrace;
+
+use eztrace::trace_args;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Default)]
+struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Counter {
+    count: u32,
+}
+
+impl Counter {
+    #[trace_args]
+    fn bump(&mut self, by: u32) -> u32 {
+        self.count += by;
+        self.count
+    }
+
+    #[trace_args]
+    fn get(&self) -> u32 {
+        self.count
+    }
+}
+
+#[trace_args]
+fn plain(a: i32, mut b: &str, _unused: u8, (x, y): (u8, u8)) -> usize {
+    b = &b[1..];
+    a as usize + b.len() + x as usize + y as usize
+}
+
+#[trace_args]
+fn generic<'a, F: Fn(u8) -> (u8, u8), T>(_f: F, map: &'a HashMap<u8, Vec<T>>, key: &u8) -> Option<&'a Vec<T>>
+where
+    T: std::fmt::Debug,
+{
+    map.get(&_f(*key).0)
+}
+
+#[trace_args]
+fn nothing() -> u8 {
+    7
+}
+
+#[test]
+fn trace_args() {
+    let buf = SharedBuf::default();
+    eztrace::set_sink(buf.clone());
+    let mut counter = Counter { count: 1 };
+    assert_eq!(counter.bump(2), 3);
+    assert_eq!(counter.get(), 3);
+    assert_eq!(plain(1, "abc", 0, (2, 3)), 8);
+    let map: HashMap<u8, Vec<char>> = HashMap::new();
+    assert_eq!(generic(|n| (n, n), &map, &4), None);
+    assert_eq!(nothing(), 7);
+    eztrace::reset_sink();
+    let out = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+    assert_eq!(
+        out,
+        "self, by: Counter { count: 1 } 2\n\
+         self: Counter { count: 3 }\n\
+         a, b: 1 \"abc\"\n\
+         map, key: {} 4\n",
+    );
+}